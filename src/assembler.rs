@@ -0,0 +1,534 @@
+use crate::config::ProcessorMode;
+use crate::disassembler::PROGRAM_START;
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// An error in the assembly source, with the line it was found on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Assembles source in a subset of the Octo language into program bytes
+/// that can be passed to `Processor::load_data`.
+///
+/// Supported are the Octo statements for every CHIP-8, SUPER-CHIP and
+/// XO-CHIP instruction, `: label` definitions, `:const NAME value`,
+/// `:call label`, calling a label by writing its name, raw data bytes
+/// written as bare numbers and `:include "file"` to embed a binary file.
+pub fn assemble(source: &str, mode: ProcessorMode) -> Result<Vec<u8>, AssembleError> {
+    assemble_relative_to(source, mode, Path::new(""))
+}
+
+/// Like `assemble`, but resolves `:include` paths relative to `directory`
+/// instead of the current one, usually the directory of the source file.
+pub fn assemble_relative_to(source: &str, mode: ProcessorMode, directory: &Path) -> Result<Vec<u8>, AssembleError> {
+    let tokens = tokenize(source);
+
+    // The first pass only finds the label addresses, which the second pass
+    // needs to resolve forward references.
+    let mut assembler = Assembler {
+        tokens: &tokens,
+        position: 0,
+        mode,
+        directory,
+        output: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        resolve: false,
+    };
+    assembler.run()?;
+
+    let labels = assembler.labels;
+    let mut assembler = Assembler {
+        tokens: &tokens,
+        position: 0,
+        mode,
+        directory,
+        output: Vec::new(),
+        labels,
+        constants: HashMap::new(),
+        resolve: true,
+    };
+    assembler.run()?;
+
+    Ok(assembler.output)
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut rest = line.trim_start();
+        while !rest.is_empty() && !rest.starts_with('#') {
+            // Quoted text like an :include path is one token, spaces and all
+            let end = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.find('"').map_or(rest.len(), |end| end + 2),
+                None => rest.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(rest.len()),
+            };
+            tokens.push(Token { text: rest[..end].to_string(), line: index + 1 });
+            rest = rest[end..].trim_start();
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        text.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+struct Assembler<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    mode: ProcessorMode,
+    /// Where `:include` paths are relative to.
+    directory: &'a Path,
+    output: Vec<u8>,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    resolve: bool,
+}
+
+impl<'a> Assembler<'a> {
+    fn run(&mut self) -> Result<(), AssembleError> {
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn error(&self, message: String) -> AssembleError {
+        let line = match self.tokens.get(self.position.saturating_sub(1)) {
+            Some(token) => token.line,
+            None => 0,
+        };
+        AssembleError { line, message }
+    }
+
+    fn next(&mut self) -> Result<&'a str, AssembleError> {
+        let tokens = self.tokens;
+        match tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(&token.text)
+            }
+            None => Err(self.error(String::from("unexpected end of source"))),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        let tokens = self.tokens;
+        tokens.get(self.position).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(self.error(format!("expected '{expected}', found '{token}'")));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        parse_register(token).ok_or_else(|| self.error(format!("expected a register, found '{token}'")))
+    }
+
+    /// Reads a number, constant or label and checks that it fits `max`.
+    fn value(&mut self, max: i64) -> Result<u16, AssembleError> {
+        let token = self.next()?;
+        let value = if let Some(value) = parse_number(token) {
+            value
+        } else if let Some(value) = self.constants.get(token) {
+            *value
+        } else if let Some(address) = self.labels.get(token) {
+            *address as i64
+        } else if !self.resolve {
+            0
+        } else {
+            return Err(self.error(format!("undefined name '{token}'")));
+        };
+
+        // Negative bytes are allowed as two's complement
+        if max == 0xFF && (-128..0).contains(&value) {
+            return Ok((value + 256) as u16);
+        }
+        if !(0..=max).contains(&value) {
+            return Err(self.error(format!("{token} does not fit in {max:#x}")));
+        }
+        Ok(value as u16)
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), AssembleError> {
+        if !instruction.is_supported(self.mode) {
+            return Err(self.error(format!("'{instruction}' is not supported in {} mode", self.mode.get_name())));
+        }
+        self.output.extend_from_slice(&instruction.encode().to_be_bytes());
+        Ok(())
+    }
+
+    fn address(&self) -> usize {
+        PROGRAM_START + self.output.len()
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+
+        if let Some(value) = parse_number(token) {
+            if !(-128..=0xFF).contains(&value) {
+                return Err(self.error(format!("data byte {token} does not fit in a byte")));
+            }
+            self.output.push(value as u8);
+            return Ok(());
+        }
+
+        if let Some(x) = parse_register(token) {
+            return self.register_statement(x);
+        }
+
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if !self.resolve && self.labels.insert(name.to_string(), self.address()).is_some() {
+                    return Err(self.error(format!("label '{name}' is defined twice")));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value(0xFFFF)?;
+                self.constants.insert(name.to_string(), value as i64);
+            }
+            ":include" => {
+                let path = self.next()?.trim_matches('"');
+                let data = fs::read(self.directory.join(path))
+                    .map_err(|e| self.error(format!("failed to include {path}: {e}")))?;
+                self.output.extend_from_slice(&data);
+            }
+            ":call" => {
+                let address = self.value(0xFFF)?;
+                self.emit(Instruction::Call(address))?;
+            }
+            "clear" => self.emit(Instruction::ClearScreen)?,
+            "return" => self.emit(Instruction::Return)?,
+            "exit" => self.emit(Instruction::Exit)?,
+            "lores" => self.emit(Instruction::LoRes)?,
+            "hires" => self.emit(Instruction::HiRes)?,
+            "scroll-down" => {
+                let n = self.value(0xF)? as u8;
+                self.emit(Instruction::ScrollDown(n))?;
+            }
+            "scroll-up" => {
+                let n = self.value(0xF)? as u8;
+                self.emit(Instruction::ScrollUp(n))?;
+            }
+            "scroll-left" => self.emit(Instruction::ScrollLeft)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight)?,
+            "jump" => {
+                let address = self.value(0xFFF)?;
+                self.emit(Instruction::Jump(address))?;
+            }
+            "jump0" => {
+                let address = self.value(0xFFF)?;
+                self.emit(Instruction::JumpWithOffset(address))?;
+            }
+            "i" => self.index_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token {
+                    "delay" => Instruction::SetDelay(x),
+                    "buzzer" => Instruction::SetSound(x),
+                    _ => Instruction::SetPitch(x),
+                })?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value(0xF)? as u8;
+                self.emit(Instruction::Draw(x, y, n))?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::Bcd(x))?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.emit(if token == "save" { Instruction::SaveRange(x, y) } else { Instruction::LoadRange(x, y) })?;
+                } else {
+                    self.emit(if token == "save" { Instruction::Store(x) } else { Instruction::Load(x) })?;
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::SaveFlags(x))?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LoadFlags(x))?;
+            }
+            "plane" => {
+                let n = self.value(0x3)? as u8;
+                self.emit(Instruction::SelectPlanes(n))?;
+            }
+            "audio" => self.emit(Instruction::LoadAudio)?,
+            "if" => self.if_statement()?,
+            _ => {
+                if let Some(value) = self.constants.get(token) {
+                    let value = *value;
+                    if !(0..=0xFF).contains(&value) {
+                        return Err(self.error(format!("constant {token} does not fit in a byte")));
+                    }
+                    self.output.push(value as u8);
+                } else if let Some(address) = self.labels.get(token) {
+                    let address = *address;
+                    if address > 0xFFF {
+                        return Err(self.error(format!("{token} at {address:#x} does not fit in 0xfff")));
+                    }
+                    self.emit(Instruction::Call(address as u16))?;
+                } else if !self.resolve && !token.starts_with(':') {
+                    // Probably a call to a label defined further down
+                    self.emit(Instruction::Call(0))?;
+                } else {
+                    return Err(self.error(format!("unknown statement '{token}'")));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+
+        if operator == ":=" {
+            match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let nn = self.value(0xFF)? as u8;
+                    return self.emit(Instruction::Random(x, nn));
+                }
+                Some("delay") => {
+                    self.next()?;
+                    return self.emit(Instruction::GetDelay(x));
+                }
+                Some("key") => {
+                    self.next()?;
+                    return self.emit(Instruction::WaitKey(x));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(y) = self.peek().and_then(parse_register) {
+            self.next()?;
+            let instruction = match operator {
+                ":=" => Instruction::Set(x, y),
+                "|=" => Instruction::Or(x, y),
+                "&=" => Instruction::And(x, y),
+                "^=" => Instruction::Xor(x, y),
+                "+=" => Instruction::Add(x, y),
+                "-=" => Instruction::Sub(x, y),
+                ">>=" => Instruction::ShiftRight(x, y),
+                "=-" => Instruction::SubReverse(x, y),
+                "<<=" => Instruction::ShiftLeft(x, y),
+                _ => return Err(self.error(format!("unknown operator '{operator}'"))),
+            };
+            return self.emit(instruction);
+        }
+
+        let nn = self.value(0xFF)? as u8;
+        let instruction = match operator {
+            ":=" => Instruction::SetImmediate(x, nn),
+            "+=" => Instruction::AddImmediate(x, nn),
+            "-=" => Instruction::AddImmediate(x, nn.wrapping_neg()),
+            _ => return Err(self.error(format!("unknown operator '{operator}' with a constant"))),
+        };
+        self.emit(instruction)
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+
+        if operator == "+=" {
+            let x = self.register()?;
+            return self.emit(Instruction::AddIndex(x));
+        }
+        if operator != ":=" {
+            return Err(self.error(format!("unknown operator '{operator}' for i")));
+        }
+
+        match self.peek() {
+            Some("hex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Instruction::Font(x))
+            }
+            Some("bighex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Instruction::BigFont(x))
+            }
+            Some("long") => {
+                self.next()?;
+                let address = self.value(0xFFFF)?;
+                self.emit(Instruction::LongIndex)?;
+                self.output.extend_from_slice(&address.to_be_bytes());
+                Ok(())
+            }
+            _ => {
+                let address = self.value(0xFFF)?;
+                self.emit(Instruction::SetIndex(address))
+            }
+        }
+    }
+
+    /// `if <condition> then` skips the next instruction when the condition is false.
+    fn if_statement(&mut self) -> Result<(), AssembleError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        let instruction = match operator {
+            "key" => Instruction::SkipIfNotKey(x),
+            "-key" => Instruction::SkipIfKey(x),
+            "==" | "!=" => {
+                if let Some(y) = self.peek().and_then(parse_register) {
+                    self.next()?;
+                    if operator == "==" { Instruction::SkipIfNotEqual(x, y) } else { Instruction::SkipIfEqual(x, y) }
+                } else {
+                    let nn = self.value(0xFF)? as u8;
+                    if operator == "==" {
+                        Instruction::SkipIfNotEqualImmediate(x, nn)
+                    } else {
+                        Instruction::SkipIfEqualImmediate(x, nn)
+                    }
+                }
+            }
+            _ => return Err(self.error(format!("unknown condition '{operator}'"))),
+        };
+
+        self.expect("then")?;
+        self.emit(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{self, Syntax};
+
+    #[test]
+    fn labels_and_constants() {
+        let source = "
+            :const SPEED 3
+            : main
+                v0 := SPEED     # sprite is a forward reference
+                i := sprite
+                sprite v0 v1 1
+                jump main
+            : sprite
+                0b10101010
+        ";
+        let rom = assemble(source, ProcessorMode::Chip8).unwrap();
+        assert_eq!(rom, vec![0x60, 0x03, 0xA2, 0x08, 0xD0, 0x11, 0x12, 0x00, 0xAA]);
+    }
+
+    #[test]
+    fn include_relative_to_directory() {
+        let directory = std::env::temp_dir().join(format!("chip8 include {}", std::process::id()));
+        fs::create_dir_all(directory.join("sprite data")).unwrap();
+        fs::write(directory.join("sprite data").join("ball #1.bin"), [0x3C, 0x7E]).unwrap();
+
+        let source = ":include \"sprite data/ball #1.bin\" 0xFF # the quoted # isn't a comment";
+        let rom = assemble_relative_to(source, ProcessorMode::Chip8, &directory);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(rom.unwrap(), vec![0x3C, 0x7E, 0xFF]);
+    }
+
+    #[test]
+    fn call_by_name() {
+        let source = "draw return : draw clear return";
+        let rom = assemble(source, ProcessorMode::Chip8).unwrap();
+        assert_eq!(rom, vec![0x22, 0x04, 0x00, 0xEE, 0x00, 0xE0, 0x00, 0xEE]);
+
+        // The label lands at 0x1000, out of reach of 2NNN
+        let source = format!("far {} : far return", "0 ".repeat(0xDFE));
+        let error = assemble(&source, ProcessorMode::XOChip).unwrap_err();
+        assert!(error.message.contains("far at 0x1000"));
+    }
+
+    #[test]
+    fn conditions() {
+        let source = "if v1 == 5 then v2 += -1 if v3 != v4 then if v5 key then";
+        let rom = assemble(source, ProcessorMode::Chip8).unwrap();
+        assert_eq!(rom, vec![0x41, 0x05, 0x72, 0xFF, 0x53, 0x40, 0xE5, 0xA1]);
+    }
+
+    #[test]
+    fn mode_check() {
+        assert!(assemble("hires", ProcessorMode::Chip8).is_err());
+        assert_eq!(assemble("hires", ProcessorMode::SuperChip).unwrap(), vec![0x00, 0xFF]);
+        assert_eq!(
+            assemble("i := long 0x1234 plane 3", ProcessorMode::XOChip).unwrap(),
+            vec![0xF0, 0x00, 0x12, 0x34, 0xF3, 0x01]
+        );
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = assemble("clear\nv0 := missing", ProcessorMode::Chip8).unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let rom = vec![
+            0x00, 0xE0, 0x60, 0x05, 0x30, 0x05, 0x12, 0x00, 0x22, 0x10, 0xA2, 0x14, 0xD0, 0x15, 0xF0, 0x33, 0x12, 0x0A,
+            0x00, 0xEE, 0xFF, 0x81,
+        ];
+        let source: Vec<String> = disassembler::disassemble(&rom, ProcessorMode::Chip8, Syntax::Octo)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(assemble(&source.join("\n"), ProcessorMode::Chip8).unwrap(), rom);
+    }
+}
@@ -0,0 +1,439 @@
+use crate::frontend::AudioSink;
+#[cfg(feature = "audio-device")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio-device")]
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig, StreamError};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+#[cfg(feature = "audio-device")]
+use std::sync::{Arc, Mutex};
+
+pub const SAMPLE_RATE: u32 = 44100;
+/// The number of samples played during one 60 Hz frame.
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+/// How far the sound device may lag behind the emulator before old samples
+/// are dropped.
+#[cfg(feature = "audio-device")]
+const MAX_QUEUED_SAMPLES: usize = SAMPLES_PER_FRAME * 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Sine => "sine",
+        }
+    }
+
+    /// The value of the wave at `phase`, which runs from 0 to 1 over one period.
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+/// The beep played while the sound timer runs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tone {
+    pub frequency: f32,
+    /// From 0 (silent) to 1 (full scale).
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// Generates `count` samples of `tone` starting at `phase`. Returns the
+/// samples and the phase to continue from, so consecutive buffers join up
+/// without clicks.
+pub fn generate(tone: &Tone, phase: f32, count: usize) -> (Vec<i16>, f32) {
+    let step = tone.frequency / SAMPLE_RATE as f32;
+    let amplitude = tone.volume.clamp(0.0, 1.0) * i16::MAX as f32;
+
+    let mut phase = phase;
+    let mut samples = Vec::with_capacity(count);
+    for _ in 0..count {
+        samples.push((tone.waveform.sample(phase) * amplitude) as i16);
+        phase = (phase + step).fract();
+    }
+    (samples, phase)
+}
+
+/// The XO-CHIP audio pattern: 128 1-bit samples played in a loop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pattern {
+    pub bits: [u8; 16],
+    pub pitch: u8,
+}
+
+impl Pattern {
+    /// The playback rate in bits per second, 4000 Hz at the default pitch of 64.
+    pub fn get_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    fn get_bit(&self, index: usize) -> bool {
+        self.bits[index / 8] >> (7 - index % 8) & 1 == 1
+    }
+}
+
+/// Resamples `count` samples of `pattern` to `SAMPLE_RATE`, starting at bit
+/// `position`. Returns the samples and the position to continue from.
+pub fn render_pattern(pattern: &Pattern, volume: f32, position: f32, count: usize) -> (Vec<i16>, f32) {
+    let step = pattern.get_rate() / SAMPLE_RATE as f32;
+    let amplitude = (volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+
+    let mut position = position;
+    let mut samples = Vec::with_capacity(count);
+    for _ in 0..count {
+        samples.push(if pattern.get_bit(position as usize) { amplitude } else { -amplitude });
+        position = (position + step) % 128.0;
+    }
+    (samples, position)
+}
+
+/// Somewhere to send mono 16-bit PCM at `SAMPLE_RATE`.
+pub trait AudioBackend {
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()>;
+}
+
+/// Writes the samples to a WAV file. The sizes in the header are filled in
+/// by `finish`, or when the writer is dropped.
+pub struct WavWriter<W: Write + Seek> {
+    // Only taken by finish
+    writer: Option<W>,
+    sample_count: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&wav_header(0))?;
+        Ok(WavWriter { writer: Some(writer), sample_count: 0 })
+    }
+
+    /// Completes the header and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut writer = self.writer.take().unwrap();
+        write_header(&mut writer, self.sample_count)?;
+        Ok(writer)
+    }
+}
+
+fn write_header<W: Write + Seek>(writer: &mut W, sample_count: u32) -> io::Result<()> {
+    let position = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&wav_header(sample_count))?;
+    writer.seek(SeekFrom::Start(position))?;
+    writer.flush()
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = write_header(writer, self.sample_count) {
+                println!("ERROR Failed to complete the WAV header: {e}");
+            }
+        }
+    }
+}
+
+impl<W: Write + Seek> AudioBackend for WavWriter<W> {
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        for sample in samples {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.sample_count += samples.len() as u32;
+        Ok(())
+    }
+}
+
+/// Plays the samples on the default sound device.
+#[cfg(feature = "audio-device")]
+pub struct DeviceOutput {
+    queue: Arc<Mutex<VecDeque<i16>>>,
+    // Set by the sound thread, reported by the next write
+    error: Arc<Mutex<Option<StreamError>>>,
+    // Plays for as long as it is kept
+    _stream: Stream,
+}
+
+#[cfg(feature = "audio-device")]
+impl DeviceOutput {
+    pub fn open() -> io::Result<DeviceOutput> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no sound device"))?;
+        let supported = device.default_output_config().map_err(io::Error::other)?;
+        let sample_format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let error = Arc::new(Mutex::new(None));
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone(), error.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone(), error.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone(), error.clone()),
+            format => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported sample format {format}")))
+            }
+        }
+        .map_err(io::Error::other)?;
+        stream.play().map_err(io::Error::other)?;
+
+        Ok(DeviceOutput { queue, error, _stream: stream })
+    }
+}
+
+#[cfg(feature = "audio-device")]
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    queue: Arc<Mutex<VecDeque<i16>>>,
+    error: Arc<Mutex<Option<StreamError>>>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<i16>,
+{
+    let channels = config.channels as usize;
+    let mut resampler = Resampler::new(config.sample_rate.0);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                let sample = T::from_sample(resampler.next(&mut queue));
+                frame.fill(sample);
+            }
+        },
+        move |e| *error.lock().unwrap() = Some(e),
+        None,
+    )
+}
+
+#[cfg(feature = "audio-device")]
+impl AudioBackend for DeviceOutput {
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+        let excess = queue.len().saturating_sub(MAX_QUEUED_SAMPLES);
+        queue.drain(..excess);
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(io::Error::other(format!("sound device failed: {e}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts samples at `SAMPLE_RATE` to the rate of the sound device by
+/// repeating or skipping samples.
+#[cfg_attr(not(feature = "audio-device"), allow(dead_code))]
+struct Resampler {
+    step: f32,
+    position: f32,
+    current: i16,
+}
+
+#[cfg_attr(not(feature = "audio-device"), allow(dead_code))]
+impl Resampler {
+    fn new(output_rate: u32) -> Resampler {
+        Resampler { step: SAMPLE_RATE as f32 / output_rate as f32, position: 0.0, current: 0 }
+    }
+
+    /// The next output sample, silence once the queue runs dry.
+    fn next(&mut self, queue: &mut VecDeque<i16>) -> i16 {
+        self.position += self.step;
+        while self.position >= 1.0 {
+            self.current = queue.pop_front().unwrap_or(0);
+            self.position -= 1.0;
+        }
+        self.current
+    }
+}
+
+/// The 44 byte header of a mono 16-bit PCM WAV file.
+fn wav_header(sample_count: u32) -> Vec<u8> {
+    let data_size = sample_count * 2;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+/// Plays `tone`, or the XO-CHIP pattern once a program loaded one, through
+/// its backends while the sound timer runs and silence otherwise.
+pub struct Beeper {
+    tone: Tone,
+    phase: f32,
+    position: f32,
+    backends: Vec<Box<dyn AudioBackend>>,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, backend: Box<dyn AudioBackend>) -> Beeper {
+        Beeper { tone, phase: 0.0, position: 0.0, backends: vec![backend] }
+    }
+
+    /// Also sends the sound to `backend`, for example to record it.
+    pub fn add_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.backends.push(backend);
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+}
+
+impl AudioSink for Beeper {
+    fn play_frame(&mut self, beeping: bool, pattern: Option<&Pattern>) {
+        let samples = match pattern {
+            _ if !beeping => {
+                self.phase = 0.0;
+                self.position = 0.0;
+                vec![0; SAMPLES_PER_FRAME]
+            }
+            Some(pattern) => {
+                let (samples, position) = render_pattern(pattern, self.tone.volume, self.position, SAMPLES_PER_FRAME);
+                self.position = position;
+                samples
+            }
+            None => {
+                let (samples, phase) = generate(&self.tone, self.phase, SAMPLES_PER_FRAME);
+                self.phase = phase;
+                samples
+            }
+        };
+
+        for backend in &mut self.backends {
+            if let Err(e) = backend.write_samples(&samples) {
+                println!("ERROR Failed to write audio: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn square_wave() {
+        let tone = Tone {
+            frequency: SAMPLE_RATE as f32 / 4.0,
+            volume: 1.0,
+            waveform: Waveform::Square,
+        };
+        let (samples, phase) = generate(&tone, 0.0, 6);
+        assert_eq!(samples, vec![i16::MAX, i16::MAX, -i16::MAX, -i16::MAX, i16::MAX, i16::MAX]);
+        assert_eq!(phase, 0.5);
+    }
+
+    #[test]
+    fn pattern_rate() {
+        let mut pattern = Pattern { bits: [0xF0; 16], pitch: 64 };
+        assert_eq!(pattern.get_rate(), 4000.0);
+        pattern.pitch = 112;
+        assert_eq!(pattern.get_rate(), 8000.0);
+    }
+
+    #[test]
+    fn resamples_pattern() {
+        // Play one bit per sample: four high bits then four low bits
+        let pattern = Pattern { bits: [0xF0; 16], pitch: 64 };
+        let step = SAMPLE_RATE as f32 / pattern.get_rate();
+        let (samples, _) = render_pattern(&pattern, 1.0, 0.0, (8.0 * step) as usize);
+        let high = samples.iter().filter(|sample| **sample > 0).count() as f32;
+        assert!((high - 4.0 * step).abs() <= 1.0);
+        assert!(samples[0] > 0);
+        assert!(samples[samples.len() - 1] < 0);
+    }
+
+    #[test]
+    fn wav_header_sizes() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        wav.write_samples(&[1, -1, 2]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 6);
+        assert_eq!(&bytes[44..], &[1, 0, 0xFF, 0xFF, 2, 0]);
+    }
+
+    #[test]
+    fn resamples_to_device_rate() {
+        let mut queue: VecDeque<i16> = (1..=4).collect();
+        let mut resampler = Resampler::new(SAMPLE_RATE * 2);
+        let samples: Vec<i16> = (0..10).map(|_| resampler.next(&mut queue)).collect();
+        assert_eq!(samples, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 0]);
+
+        let mut queue: VecDeque<i16> = (1..=4).collect();
+        let mut resampler = Resampler::new(SAMPLE_RATE / 2);
+        assert_eq!(resampler.next(&mut queue), 2);
+        assert_eq!(resampler.next(&mut queue), 4);
+    }
+
+    #[test]
+    fn wav_header_on_drop() {
+        let mut bytes = Vec::new();
+        {
+            let mut wav = WavWriter::new(Cursor::new(&mut bytes)).unwrap();
+            wav.write_samples(&[1, 2]).unwrap();
+        }
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 4);
+    }
+}
@@ -0,0 +1,64 @@
+use chip8_emulator::{assembler, config::ProcessorMode};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+const USAGE: &str = "Usage: chip8-asm [--mode chip-8|superchip|xochip] [-o <out.ch8>] <source.8o>";
+
+fn main() {
+    let mut mode = ProcessorMode::Chip8;
+    let mut source_path = None;
+    let mut output_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().as_deref().and_then(ProcessorMode::from_name) {
+                    Some(mode) => mode,
+                    None => fail("ERROR --mode expects chip-8, superchip or xochip"),
+                }
+            }
+            "-o" | "--output" => {
+                output_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR -o expects a file name"),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => source_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let source_path = match source_path {
+        Some(path) => path,
+        None => fail(USAGE),
+    };
+    let output_path = output_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+
+    let source = match fs::read_to_string(&source_path) {
+        Ok(source) => source,
+        Err(e) => fail(&format!("ERROR Failed to read {}: {e}", source_path.display())),
+    };
+
+    // Includes are relative to the source file
+    let directory = source_path.parent().unwrap_or(Path::new(""));
+    let rom = match assembler::assemble_relative_to(&source, mode, directory) {
+        Ok(rom) => rom,
+        Err(e) => fail(&format!("ERROR {}: {e}", source_path.display())),
+    };
+
+    if let Err(e) = fs::write(&output_path, &rom) {
+        fail(&format!("ERROR Failed to write {}: {e}", output_path.display()));
+    }
+
+    println!("Wrote {} bytes to {}", rom.len(), output_path.display());
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
@@ -0,0 +1,54 @@
+use chip8_emulator::{config::ProcessorMode, disassembler::{self, Syntax}, rom};
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "Usage: chip8-disasm [--mode chip-8|superchip|xochip] [--syntax octo|cowgod] <rom.ch8>";
+
+fn main() {
+    let mut mode = ProcessorMode::Chip8;
+    let mut syntax = Syntax::Octo;
+    let mut rom_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().as_deref().and_then(ProcessorMode::from_name) {
+                    Some(mode) => mode,
+                    None => fail("ERROR --mode expects chip-8, superchip or xochip"),
+                }
+            }
+            "--syntax" => {
+                syntax = match args.next().as_deref().and_then(Syntax::from_name) {
+                    Some(syntax) => syntax,
+                    None => fail("ERROR --syntax expects octo or cowgod"),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => rom_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => fail(USAGE),
+    };
+
+    let rom = match rom::load(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => fail(&format!("ERROR Failed to load {}: {e}", rom_path.display())),
+    };
+
+    for line in disassembler::disassemble(&rom, mode, syntax) {
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+        println!("{:04X}  {:<24}{}", line.address, bytes.join(" "), line.text);
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
@@ -0,0 +1,363 @@
+use chip8_emulator::{
+    audio::{Beeper, Tone, WavWriter},
+    config::ProcessorMode,
+    frontend::AudioSink,
+    dump,
+    emulator::{Emulator, INSTRUCTIONS_PER_FRAME},
+    instruction::Instruction,
+    movie::Movie,
+    processor::Processor,
+    quirks::QuirksProfile,
+    random::{self, RandomMode},
+    rom,
+    trace::{TraceFilter, TraceFormat, Tracer},
+};
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "Usage: chip8-headless [options] <rom.ch8>
+
+Options:
+  --mode chip-8|superchip|xochip   Processor variant (default chip-8)
+  --quirks <profile>               cosmac-vip, chip-48, schip-1.0, schip-1.1 or xo-chip
+  --frames <n>                     Number of frames to run (default 600, or the movie length)
+  --ipf <n>                        Instructions per frame (default 15)
+  --key <frame>:<key>:<frames>     Hold hex key from a frame on for a number of frames
+  --stop-on-loop                   Stop when the program jumps to itself
+  --strict                         Stop on unknown opcodes
+  --seed <n>                       Seed the random number generator
+  --random seeded|cosmac-vip       Random number generator (default seeded)
+  --vip-interpreter <file>         COSMAC VIP interpreter dump used by cosmac-vip
+  --format ascii|pbm               Framebuffer dump format (default ascii)
+  --screen <file>                  Write the framebuffer to a file instead of stdout
+  --wav <file>                     Record the sound to a WAV file
+  --record <file>                  Record the keys to a movie file
+  --play <file>                    Replay a movie, using the settings it was recorded with
+  --trace <file>                   Log every executed instruction
+  --trace-format text|binary       Trace format (default text)
+  --trace-pc <ranges>              Only trace these addresses, e.g. 0x200-0x2ff,0x300
+  --trace-opcodes <classes>        Only trace opcodes starting with these hex digits, e.g. 8,D
+  --trace-cycles <n>               Only trace the first n instructions
+  --hash-every <n>                 Print the SHA-256 of the screen and state every n frames,
+                                   the state only repeats between runs with --seed";
+
+struct KeyPress {
+    frame: u64,
+    key: usize,
+    frames: u64,
+}
+
+enum Format {
+    Ascii,
+    Pbm,
+}
+
+fn main() {
+    let mut mode = ProcessorMode::Chip8;
+    let mut quirks = None;
+    let mut frames = None;
+    let mut instructions_per_frame = INSTRUCTIONS_PER_FRAME;
+    let mut key_presses = Vec::new();
+    let mut stop_on_loop = false;
+    let mut strict = false;
+    let mut seed = None;
+    let mut random_mode = RandomMode::Seeded;
+    let mut vip_interpreter = None;
+    let mut format = Format::Ascii;
+    let mut screen_path = None;
+    let mut wav_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+    let mut hash_every = None;
+    let mut rom_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().as_deref().and_then(ProcessorMode::from_name) {
+                    Some(mode) => mode,
+                    None => fail("ERROR --mode expects chip-8, superchip or xochip"),
+                }
+            }
+            "--quirks" => {
+                quirks = match args.next().as_deref().and_then(QuirksProfile::from_name) {
+                    Some(profile) => Some(profile.quirks()),
+                    None => fail("ERROR Unknown quirks profile"),
+                }
+            }
+            "--frames" => frames = Some(parse_number(args.next(), "--frames")),
+            "--ipf" => instructions_per_frame = parse_number(args.next(), "--ipf"),
+            "--key" => key_presses.push(parse_key_press(args.next())),
+            "--stop-on-loop" => stop_on_loop = true,
+            "--strict" => strict = true,
+            "--seed" => seed = Some(parse_number(args.next(), "--seed")),
+            "--random" => {
+                random_mode = match args.next().as_deref().and_then(RandomMode::from_name) {
+                    Some(random_mode) => random_mode,
+                    None => fail("ERROR --random expects seeded or cosmac-vip"),
+                }
+            }
+            "--vip-interpreter" => {
+                vip_interpreter = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --vip-interpreter expects a file name"),
+                }
+            }
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("ascii") => Format::Ascii,
+                    Some("pbm") => Format::Pbm,
+                    _ => fail("ERROR --format expects ascii or pbm"),
+                }
+            }
+            "--screen" => {
+                screen_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --screen expects a file name"),
+                }
+            }
+            "--wav" => {
+                wav_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --wav expects a file name"),
+                }
+            }
+            "--record" => {
+                record_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --record expects a file name"),
+                }
+            }
+            "--play" => {
+                play_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --play expects a file name"),
+                }
+            }
+            "--trace" => {
+                trace_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --trace expects a file name"),
+                }
+            }
+            "--trace-format" => {
+                trace_format = match args.next().as_deref().and_then(TraceFormat::from_name) {
+                    Some(trace_format) => trace_format,
+                    None => fail("ERROR --trace-format expects text or binary"),
+                }
+            }
+            "--trace-pc" => {
+                trace_filter.pc_ranges = match args.next().as_deref().and_then(TraceFilter::parse_pc_ranges) {
+                    Some(pc_ranges) => pc_ranges,
+                    None => fail("ERROR --trace-pc expects address ranges like 0x200-0x2ff,0x300"),
+                }
+            }
+            "--trace-opcodes" => {
+                trace_filter.opcode_classes = match args.next().as_deref().and_then(TraceFilter::parse_opcode_classes) {
+                    Some(opcode_classes) => opcode_classes,
+                    None => fail("ERROR --trace-opcodes expects hex digits like 8,D"),
+                }
+            }
+            "--trace-cycles" => trace_filter.max_cycles = Some(parse_number(args.next(), "--trace-cycles")),
+            "--hash-every" => {
+                hash_every = match parse_number(args.next(), "--hash-every") {
+                    0 => fail("ERROR --hash-every expects a number above 0"),
+                    n => Some(n),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => rom_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => fail(USAGE),
+    };
+    let rom = match rom::load(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => fail(&format!("ERROR Failed to load {}: {e}", rom_path.display())),
+    };
+
+    let movie = play_path.map(|path| {
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) => fail(&format!("ERROR Failed to read {}: {e}", path.display())),
+        };
+        match Movie::from_bytes(&data) {
+            Ok(movie) => movie,
+            Err(e) => fail(&format!("ERROR Failed to load {}: {e}", path.display())),
+        }
+    });
+
+    let mut emulator = match &movie {
+        Some(movie) => match movie.create_emulator(&rom) {
+            Ok(emulator) => emulator,
+            Err(e) => fail(&format!("ERROR Failed to play movie: {e}")),
+        },
+        None => {
+            let mut processor = Processor::new(mode);
+            if let Some(quirks) = quirks {
+                processor.set_quirks(quirks);
+            }
+            if let Err(e) = processor.load_data(&rom) {
+                fail(&format!("ERROR Failed to load {}: {e}", rom_path.display()));
+            }
+            if let Some(seed) = seed {
+                processor.set_seed(seed);
+            }
+            if random_mode == RandomMode::CosmacVip {
+                let path = match &vip_interpreter {
+                    Some(path) => path,
+                    None => fail("ERROR --random cosmac-vip needs --vip-interpreter"),
+                };
+                match random::load_vip_page(path) {
+                    Ok(page) => processor.set_vip_random(Some(page)),
+                    Err(e) => fail(&format!("ERROR Failed to load {}: {e}", path.display())),
+                }
+            }
+            processor.set_strict(strict);
+            let mut emulator = Emulator::new(processor);
+            emulator.set_instructions_per_frame(instructions_per_frame);
+            emulator
+        }
+    };
+    let frames = match (frames, &movie) {
+        (Some(frames), _) => frames,
+        (None, Some(movie)) => movie.frames.len() as u64,
+        (None, None) => 600,
+    };
+    if let Some(path) = &trace_path {
+        match Tracer::create(path, trace_format, trace_filter) {
+            Ok(tracer) => emulator.set_tracer(Some(tracer)),
+            Err(e) => fail(&format!("ERROR Failed to create {}: {e}", path.display())),
+        }
+    }
+    let mut recording = record_path.as_ref().map(|_| Movie::new(&emulator));
+
+    let mut beeper = wav_path.map(|path| match WavWriter::create(&path) {
+        Ok(wav) => Beeper::new(Tone::default(), Box::new(wav)),
+        Err(e) => fail(&format!("ERROR Failed to create {}: {e}", path.display())),
+    });
+
+    let mut fault = None;
+    let mut frame = 0;
+    while frame < frames {
+        let mut keys = match &movie {
+            Some(movie) => movie.get_keys(frame as usize).unwrap_or_default(),
+            None => [false; 16],
+        };
+        for press in &key_presses {
+            if (press.frame..press.frame + press.frames).contains(&frame) {
+                keys[press.key] = true;
+            }
+        }
+        if let Some(recording) = &mut recording {
+            recording.record(&keys);
+        }
+        frame += 1;
+
+        let result = emulator.run_frame(&keys);
+        if let Some(beeper) = &mut beeper {
+            beeper.play_frame(result.beeping, result.pattern.as_ref());
+        }
+        if hash_every.is_some_and(|n: u64| frame.is_multiple_of(n)) {
+            let processor = emulator.get_processor();
+            println!(
+                "Frame {frame}: screen={} state={}",
+                rom::hash_to_hex(&processor.framebuffer_hash()),
+                rom::hash_to_hex(&processor.state_hash())
+            );
+        }
+        if result.error.is_some() {
+            fault = result.error;
+            break;
+        }
+        if result.exited || (stop_on_loop && is_jump_to_self(emulator.get_processor())) {
+            break;
+        }
+    }
+
+    if let Some(tracer) = emulator.get_tracer_mut() {
+        tracer.flush();
+    }
+
+    if let (Some(path), Some(recording)) = (&record_path, &recording) {
+        if let Err(e) = fs::write(path, recording.to_bytes()) {
+            fail(&format!("ERROR Failed to write {}: {e}", path.display()));
+        }
+    }
+
+    let processor = emulator.get_processor();
+    let screen = match format {
+        Format::Ascii => dump::framebuffer_to_ascii(processor.get_framebuffer()),
+        Format::Pbm => dump::framebuffer_to_pbm(processor.get_framebuffer()),
+    };
+    match screen_path {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, screen) {
+                fail(&format!("ERROR Failed to write {}: {e}", path.display()));
+            }
+        }
+        None => print!("{screen}"),
+    }
+
+    println!("Frames: {frame}");
+    print!("{}", dump::registers(processor));
+
+    if let Some(e) = fault {
+        eprintln!("ERROR Program halted: {e}");
+        exit(2);
+    }
+}
+
+/// True if the next instruction jumps to itself, which is how most test
+/// programs end.
+fn is_jump_to_self(processor: &Processor) -> bool {
+    let pc = processor.get_pc();
+    let ram = processor.get_ram();
+    match (ram.get(pc), ram.get(pc + 1)) {
+        (Some(high), Some(low)) => {
+            let opcode = (*high as u16) << 8 | *low as u16;
+            Instruction::decode(opcode) == Some(Instruction::Jump(pc as u16))
+        }
+        _ => false,
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<String>, option: &str) -> T {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(value)) => value,
+        _ => fail(&format!("ERROR {option} expects a number")),
+    }
+}
+
+fn parse_key_press(value: Option<String>) -> KeyPress {
+    let value = value.unwrap_or_default();
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 3 {
+        fail("ERROR --key expects <frame>:<key>:<frames>");
+    }
+    let key = match usize::from_str_radix(parts[1], 16) {
+        Ok(key) if key < 16 => key,
+        _ => fail("ERROR --key expects a hex key between 0 and F"),
+    };
+    KeyPress {
+        frame: parse_number(Some(parts[0].to_string()), "--key"),
+        key,
+        frames: parse_number(Some(parts[2].to_string()), "--key"),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
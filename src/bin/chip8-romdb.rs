@@ -0,0 +1,98 @@
+use chip8_emulator::{rom, romdb};
+use ini::Ini;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "Usage: chip8-romdb [--output <roms.ini>] <programs.json> <rom or directory>...
+
+Converts the entries of the community CHIP-8 database for the given roms into
+rom database entries. Its programs.json identifies roms by SHA-1, so only the
+roms that are given can be converted. The entries are printed, or added to
+the output file, replacing those for the same roms.";
+
+fn main() {
+    let mut output = None;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => fail("ERROR --output expects a file"),
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.len() < 2 {
+        fail(USAGE);
+    }
+    let programs_path = paths.remove(0);
+    let programs = match fs::read_to_string(&programs_path) {
+        Ok(programs) => programs,
+        Err(e) => fail(&format!("ERROR Failed to read {}: {e}", programs_path.display())),
+    };
+
+    let mut roms = Vec::new();
+    for path in paths {
+        let files = match fs::read_dir(&path) {
+            Ok(entries) => {
+                let files = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()));
+                files.filter(|path| path.is_file()).collect()
+            }
+            Err(_) => vec![path],
+        };
+        for file in files {
+            match rom::load(&file) {
+                Ok(rom) => roms.push(rom),
+                Err(e) => println!("ERROR Failed to load {}: {e}", file.display()),
+            }
+        }
+    }
+
+    let entries = match romdb::import_community(&programs, &roms) {
+        Ok(entries) => entries,
+        Err(e) => fail(&format!("ERROR Failed to parse {}: {e}", programs_path.display())),
+    };
+    let found = entries.sections().flatten().count();
+
+    match output {
+        Some(output) => {
+            let mut database = match fs::read_to_string(&output) {
+                Ok(text) => match Ini::load_from_str(&text) {
+                    Ok(database) => database,
+                    Err(e) => fail(&format!("ERROR Failed to parse {}: {e}", output.display())),
+                },
+                Err(_) => Ini::new(),
+            };
+            for (section, properties) in &entries {
+                if let Some(section) = section {
+                    database.delete(Some(section));
+                    for (key, value) in properties.iter() {
+                        database.with_section(Some(section)).set(key, value);
+                    }
+                }
+            }
+            if let Err(e) = database.write_to_file(&output) {
+                fail(&format!("ERROR Failed to write {}: {e}", output.display()));
+            }
+        }
+        None => {
+            let mut text = Vec::new();
+            entries.write_to(&mut text).expect("writing to memory doesn't fail");
+            print!("{}", String::from_utf8_lossy(&text));
+        }
+    }
+    eprintln!("Found {found} of {} roms in the database", roms.len());
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
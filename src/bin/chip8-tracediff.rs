@@ -0,0 +1,282 @@
+use chip8_emulator::{
+    config::ProcessorMode,
+    emulator::{Emulator, FrameResult, INSTRUCTIONS_PER_FRAME},
+    processor::Processor,
+    quirks::QuirksProfile,
+    rom,
+    tracediff::{self, StateLine},
+};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+const USAGE: &str = "Usage: chip8-tracediff [options] <rom.ch8> [reference.log]
+       chip8-tracediff --compare <expected.log> <actual.log>
+
+Runs the rom and reports the first instruction after which the state differs
+from the reference log of another interpreter. The log format is described
+at tracediff::StateLine.
+
+Options:
+  --mode chip-8|superchip|xochip   Processor variant (default chip-8)
+  --quirks <profile>               cosmac-vip, chip-48, schip-1.0, schip-1.1 or xo-chip
+  --ipf <n>                        Instructions per frame (default 15)
+  --seed <n>                       Seed the random number generator
+  --key <frame>:<key>:<frames>     Hold hex key from a frame on for a number of frames
+  --cycles <n>                     Number of instructions to run (default the reference length)
+  --export <file>                  Write the run as a log in the reference format
+  --compare                        Compare two logs instead of running a rom";
+
+// The process exit code when the logs differ
+const DIVERGED: i32 = 2;
+
+struct KeyPress {
+    frame: u64,
+    key: usize,
+    frames: u64,
+}
+
+fn main() {
+    let mut mode = ProcessorMode::Chip8;
+    let mut quirks = None;
+    let mut instructions_per_frame = INSTRUCTIONS_PER_FRAME;
+    let mut seed = None;
+    let mut key_presses = Vec::new();
+    let mut cycles = None;
+    let mut export_path = None;
+    let mut compare_logs = false;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = match args.next().as_deref().and_then(ProcessorMode::from_name) {
+                    Some(mode) => mode,
+                    None => fail("ERROR --mode expects chip-8, superchip or xochip"),
+                }
+            }
+            "--quirks" => {
+                quirks = match args.next().as_deref().and_then(QuirksProfile::from_name) {
+                    Some(profile) => Some(profile.quirks()),
+                    None => fail("ERROR Unknown quirks profile"),
+                }
+            }
+            "--ipf" => {
+                instructions_per_frame = match parse_number(args.next(), "--ipf") {
+                    0 => fail("ERROR --ipf expects a number above 0"),
+                    ipf => ipf,
+                }
+            }
+            "--seed" => seed = Some(parse_number(args.next(), "--seed")),
+            "--key" => key_presses.push(parse_key_press(args.next())),
+            "--cycles" => cycles = Some(parse_number(args.next(), "--cycles")),
+            "--export" => {
+                export_path = match args.next() {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => fail("ERROR --export expects a file name"),
+                }
+            }
+            "--compare" => compare_logs = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if compare_logs {
+        match paths.as_slice() {
+            [expected, actual] => compare(&load_log(expected), &load_log(actual)),
+            _ => fail(USAGE),
+        }
+        return;
+    }
+
+    let (rom_path, reference) = match paths.as_slice() {
+        [rom_path] => (rom_path, None),
+        [rom_path, reference_path] => (rom_path, Some(load_log(reference_path))),
+        _ => fail(USAGE),
+    };
+    let cycles = match (cycles, &reference) {
+        (Some(cycles), _) => cycles,
+        (None, Some(reference)) => reference.last().map_or(0, |state| state.cycle + 1),
+        (None, None) => fail("ERROR --cycles is needed without a reference log"),
+    };
+    let rom = match rom::load(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => fail(&format!("ERROR Failed to load {}: {e}", rom_path.display())),
+    };
+
+    let mut processor = Processor::new(mode);
+    if let Some(quirks) = quirks {
+        processor.set_quirks(quirks);
+    }
+    if let Err(e) = processor.load_data(&rom) {
+        fail(&format!("ERROR Failed to load {}: {e}", rom_path.display()));
+    }
+    if let Some(seed) = seed {
+        processor.set_seed(seed);
+    }
+    let mut emulator = Emulator::new(processor);
+    emulator.set_instructions_per_frame(instructions_per_frame);
+
+    let reference = reference.unwrap_or_default();
+    // Compare the memory the reference looks at
+    let mut addresses: Vec<usize> = reference.iter().flat_map(|state| state.memory.iter().map(|(address, _)| *address)).collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    let mut expected = reference.iter().peekable();
+    let mut export = String::new();
+    let mut cycle = 0;
+    let mut frame = 0;
+    'run: while cycle < cycles {
+        let mut keys = [false; 16];
+        for press in &key_presses {
+            if (press.frame..press.frame + press.frames).contains(&frame) {
+                keys[press.key] = true;
+            }
+        }
+
+        let mut result = FrameResult::default();
+        emulator.start_frame(&keys);
+        loop {
+            let processor = emulator.get_processor();
+            let pc = processor.get_pc();
+            let opcode = match (processor.get_ram().get(pc), processor.get_ram().get(pc + 1)) {
+                (Some(high), Some(low)) => (*high as u16) << 8 | *low as u16,
+                _ => 0,
+            };
+            if !emulator.step_frame(&mut result) {
+                break;
+            }
+
+            let actual = StateLine::capture(cycle, pc as u16, opcode, emulator.get_processor(), &addresses);
+            if export_path.is_some() {
+                writeln!(export, "{actual}").unwrap();
+            }
+            while expected.next_if(|state| state.cycle < cycle).is_some() {}
+            if let Some(state) = expected.next_if(|state| state.cycle == cycle) {
+                let differences = tracediff::compare(state, &actual);
+                if !differences.is_empty() {
+                    write_export(&export_path, &export);
+                    report(frame, state, &actual, &differences);
+                    exit(DIVERGED);
+                }
+            }
+
+            cycle += 1;
+            if let Some(e) = result.error {
+                if expected.peek().is_some() {
+                    write_export(&export_path, &export);
+                    println!("Divergence at cycle {cycle} in frame {frame}: the program halted: {e}");
+                    exit(DIVERGED);
+                }
+                break 'run;
+            }
+            if cycle >= cycles {
+                break;
+            }
+        }
+        emulator.finish_frame(&mut result);
+        frame += 1;
+
+        if result.exited {
+            break;
+        }
+    }
+
+    write_export(&export_path, &export);
+    if expected.peek().is_some() {
+        println!("Divergence at cycle {cycle} in frame {frame}: the program exited before the reference ended");
+        exit(DIVERGED);
+    }
+    if !reference.is_empty() {
+        println!("No divergence in {cycle} cycles");
+    }
+}
+
+/// Compares two logs at the cycles both contain.
+fn compare(expected: &[StateLine], actual: &[StateLine]) {
+    let mut actual_lines = actual.iter().peekable();
+    let mut compared = 0;
+    for state in expected {
+        while actual_lines.next_if(|other| other.cycle < state.cycle).is_some() {}
+        if let Some(other) = actual_lines.next_if(|other| other.cycle == state.cycle) {
+            let differences = tracediff::compare(state, other);
+            if !differences.is_empty() {
+                println!("Divergence at cycle {}", state.cycle);
+                println!("  Expected: {}", tracediff::describe(state));
+                println!("  Actual:   {}", tracediff::describe(other));
+                for difference in &differences {
+                    println!("  {difference}");
+                }
+                exit(DIVERGED);
+            }
+            compared += 1;
+        }
+    }
+    println!("No divergence in {compared} common cycles");
+}
+
+fn report(frame: u64, expected: &StateLine, actual: &StateLine, differences: &[tracediff::Difference]) {
+    println!("Divergence at cycle {} in frame {frame}", expected.cycle);
+    if expected.pc.is_some() || expected.opcode.is_some() {
+        println!("  Expected: {}", tracediff::describe(expected));
+    }
+    println!("  Executed: {}", tracediff::describe(actual));
+    for difference in differences {
+        println!("  {difference}");
+    }
+}
+
+fn load_log(path: &PathBuf) -> Vec<StateLine> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => fail(&format!("ERROR Failed to read {}: {e}", path.display())),
+    };
+    match tracediff::parse_log(&text) {
+        Ok(log) => log,
+        Err(e) => fail(&format!("ERROR Failed to parse {}: {e}", path.display())),
+    }
+}
+
+fn write_export(path: &Option<PathBuf>, export: &str) {
+    if let Some(path) = path {
+        if let Err(e) = fs::write(path, export) {
+            fail(&format!("ERROR Failed to write {}: {e}", path.display()));
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<String>, option: &str) -> T {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(value)) => value,
+        _ => fail(&format!("ERROR {option} expects a number")),
+    }
+}
+
+fn parse_key_press(value: Option<String>) -> KeyPress {
+    let value = value.unwrap_or_default();
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 3 {
+        fail("ERROR --key expects <frame>:<key>:<frames>");
+    }
+    let key = match usize::from_str_radix(parts[1], 16) {
+        Ok(key) if key < 16 => key,
+        _ => fail("ERROR --key expects a hex key between 0 and F"),
+    };
+    KeyPress {
+        frame: parse_number(Some(parts[0].to_string()), "--key"),
+        key,
+        frames: parse_number(Some(parts[2].to_string()), "--key"),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
@@ -1,12 +1,39 @@
+use crate::audio::{Tone, Waveform};
+use crate::emulator::INSTRUCTIONS_PER_FRAME;
+use crate::random::RandomMode;
+use crate::quirks::{Quirks, QuirksProfile};
+use crate::trace::{TraceFilter, TraceFormat};
 use ini::Ini;
 use std::{fs, io::Write};
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 pub enum ProcessorMode {
+    #[default]
     Chip8,
     SuperChip,
     XOChip,
 }
 
+impl ProcessorMode {
+    /// Looks up a mode by the name used in config.ini and on the command line.
+    pub fn from_name(name: &str) -> Option<ProcessorMode> {
+        match name {
+            "chip-8" => Some(ProcessorMode::Chip8),
+            "superchip" => Some(ProcessorMode::SuperChip),
+            "xochip" => Some(ProcessorMode::XOChip),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ProcessorMode::Chip8 => "chip-8",
+            ProcessorMode::SuperChip => "superchip",
+            ProcessorMode::XOChip => "xochip",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Palette {
     name: String,
@@ -14,10 +41,39 @@ pub struct Palette {
     off_color: u32,
 }
 
+#[derive(Clone)]
 pub struct Config {
     pub mode: ProcessorMode,
+    pub quirks: Quirks,
+    /// Overrides the number of nested calls the stack holds.
+    pub stack_limit: Option<usize>,
+    /// Stop programs on unknown opcodes instead of skipping them.
+    pub strict: bool,
+    /// Read debugger commands from stdin while the window runs.
+    pub debug_console: bool,
+    /// Serves the GDB remote protocol on this local port.
+    pub gdb_port: Option<u16>,
+    /// Logs every executed instruction to this file.
+    pub trace_output: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub instructions_per_frame: u32,
+    /// Seeds CXNN, a new seed is picked every run if unset.
+    pub seed: Option<u64>,
+    pub random: RandomMode,
+    /// A dump of the COSMAC VIP interpreter, needed by `RandomMode::CosmacVip`.
+    pub vip_interpreter: Option<String>,
     pub on_color: u32,
     pub off_color: u32,
+    pub plane2_color: u32,
+    pub overlap_color: u32,
+    pub tone: Tone,
+    /// How far back the rewind key goes, 0 turns rewinding off.
+    pub rewind_seconds: u32,
+    /// Caps the memory used by the rewind buffer.
+    pub rewind_memory_mb: u32,
+    /// Also records the sound to this WAV file.
+    pub wav_output: Option<String>,
     palettes: Vec<Palette>,
 }
 
@@ -25,8 +81,26 @@ impl Config {
     pub fn build() -> std::io::Result<Config> {
         let mut conf: Config = Config {
             mode: ProcessorMode::Chip8,
+            quirks: Quirks::default(),
+            stack_limit: None,
+            strict: false,
+            debug_console: false,
+            gdb_port: None,
+            trace_output: None,
+            trace_format: TraceFormat::Text,
+            trace_filter: TraceFilter::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            seed: None,
+            random: RandomMode::Seeded,
+            vip_interpreter: None,
             on_color: 0x00FF00,
             off_color: 0x0,
+            plane2_color: 0xFF6600,
+            overlap_color: 0x662200,
+            tone: Tone::default(),
+            rewind_seconds: 10,
+            rewind_memory_mb: 32,
+            wav_output: None,
             palettes: vec![
                 Palette {
                     name: String::from("Matrix"),
@@ -103,27 +177,137 @@ impl Config {
                 for (_sec, prop) in &ini {
                     for (key, value) in prop.iter() {
                         match key {
-                            "mode" => {
-                                conf.mode = match value {
-                                    "chip-8" => ProcessorMode::Chip8,
-                                    "superchip" => ProcessorMode::SuperChip,
-                                    "xochip" => ProcessorMode::XOChip,
-                                    _ => ProcessorMode::Chip8,
-                                };
+                            "mode" => match ProcessorMode::from_name(value) {
+                                Some(mode) => conf.mode = mode,
+                                None => println!("ERROR Unknown mode {value}, using {}", conf.mode.get_name()),
+                            },
+                            "stack_limit" => {
+                                conf.stack_limit = match value.parse() {
+                                    Ok(val) => Some(val),
+                                    Err(e) => {
+                                        println!("ERROR Failed to parse stack_limit: {e}");
+                                        None
+                                    }
+                                }
+                            }
+                            "strict" => conf.strict = value == "true",
+                            "debug_console" => conf.debug_console = value == "true",
+                            "gdb_port" => {
+                                conf.gdb_port = match value.parse() {
+                                    Ok(val) => Some(val),
+                                    Err(e) => {
+                                        println!("ERROR Failed to parse gdb_port: {e}");
+                                        None
+                                    }
+                                }
+                            }
+                            "instructions_per_frame" => {
+                                match value.parse() {
+                                    Ok(val) if val > 0 => conf.instructions_per_frame = val,
+                                    _ => println!("ERROR Failed to parse instructions_per_frame: {value}"),
+                                }
                             }
                             "on_color" => conf.on_color = u32::from_str_radix(&value.replace("0x", ""), 16).unwrap(),
                             "off_color" => conf.off_color = u32::from_str_radix(&value.replace("0x", ""), 16).unwrap(),
+                            "plane2_color" => {
+                                match u32::from_str_radix(&value.replace("0x", ""), 16) {
+                                    Ok(val) => conf.plane2_color = val,
+                                    Err(e) => println!("ERROR Failed to parse plane2_color: {e}"),
+                                }
+                            }
+                            "overlap_color" => {
+                                match u32::from_str_radix(&value.replace("0x", ""), 16) {
+                                    Ok(val) => conf.overlap_color = val,
+                                    Err(e) => println!("ERROR Failed to parse overlap_color: {e}"),
+                                }
+                            }
+                            "seed" => {
+                                conf.seed = match value.parse() {
+                                    Ok(val) => Some(val),
+                                    Err(e) => {
+                                        println!("ERROR Failed to parse seed: {e}");
+                                        None
+                                    }
+                                }
+                            }
+                            "random" => {
+                                match RandomMode::from_name(value) {
+                                    Some(random) => conf.random = random,
+                                    None => println!("ERROR Unknown random mode {value}"),
+                                }
+                            }
+                            "vip_interpreter" => conf.vip_interpreter = Some(value.to_string()),
+                            "frequency" => {
+                                match value.parse() {
+                                    Ok(val) => conf.tone.frequency = val,
+                                    Err(e) => println!("ERROR Failed to parse frequency: {e}"),
+                                }
+                            }
+                            "volume" => {
+                                match value.parse() {
+                                    Ok(val) => conf.tone.volume = val,
+                                    Err(e) => println!("ERROR Failed to parse volume: {e}"),
+                                }
+                            }
+                            "waveform" => {
+                                match Waveform::from_name(value) {
+                                    Some(waveform) => conf.tone.waveform = waveform,
+                                    None => println!("ERROR Unknown waveform {value}"),
+                                }
+                            }
+                            "wav_output" => conf.wav_output = Some(value.to_string()),
+                            "rewind_seconds" => {
+                                match value.parse() {
+                                    Ok(val) => conf.rewind_seconds = val,
+                                    Err(e) => println!("ERROR Failed to parse rewind_seconds: {e}"),
+                                }
+                            }
+                            "rewind_memory_mb" => {
+                                match value.parse() {
+                                    Ok(val) => conf.rewind_memory_mb = val,
+                                    Err(e) => println!("ERROR Failed to parse rewind_memory_mb: {e}"),
+                                }
+                            }
+                            "trace_output" => conf.trace_output = Some(value.to_string()),
+                            "trace_format" => {
+                                match TraceFormat::from_name(value) {
+                                    Some(trace_format) => conf.trace_format = trace_format,
+                                    None => println!("ERROR Unknown trace format {value}"),
+                                }
+                            }
+                            "trace_pc" => {
+                                match TraceFilter::parse_pc_ranges(value) {
+                                    Some(pc_ranges) => conf.trace_filter.pc_ranges = pc_ranges,
+                                    None => println!("ERROR Failed to parse trace_pc: {value}"),
+                                }
+                            }
+                            "trace_opcodes" => {
+                                match TraceFilter::parse_opcode_classes(value) {
+                                    Some(opcode_classes) => conf.trace_filter.opcode_classes = opcode_classes,
+                                    None => println!("ERROR Failed to parse trace_opcodes: {value}"),
+                                }
+                            }
+                            "trace_cycles" => {
+                                match value.parse() {
+                                    Ok(val) => conf.trace_filter.max_cycles = Some(val),
+                                    Err(e) => println!("ERROR Failed to parse trace_cycles: {e}"),
+                                }
+                            }
                             _ => {}
                         }
                     }
                 }
+                conf.quirks = Self::read_quirks(&ini, conf.mode);
             }
             Err(..) => {
                 let mut ini = Ini::new();
                 ini.with_section(Some("General")).set("mode", "chip-8");
                 ini.with_section(Some("Colors"))
                     .set("on_color", format!("{:#04x}", conf.on_color))
-                    .set("off_color", format!("{:#04x}", conf.off_color));
+                    .set("off_color", format!("{:#04x}", conf.off_color))
+                    .set("plane2_color", format!("{:#04x}", conf.plane2_color))
+                    .set("overlap_color", format!("{:#04x}", conf.overlap_color));
+                Self::write_quirks(&mut ini, &conf.quirks);
                 ini.write_to_file("config.ini")?;
             }
         };
@@ -136,18 +320,106 @@ impl Config {
 
     pub fn save_to_file(&self) -> std::io::Result<()>{
         let mut ini = Ini::new();
-        ini.with_section(Some("General")).set("mode", match self.mode {
-            ProcessorMode::Chip8 => "chip-8",
-            ProcessorMode::SuperChip => "superchip",
-            ProcessorMode::XOChip => "xochip"
-        });
+        ini.with_section(Some("General")).set("mode", self.mode.get_name());
+        ini.with_section(Some("General")).set("strict", self.strict.to_string());
+        ini.with_section(Some("General")).set("debug_console", self.debug_console.to_string());
+        if let Some(gdb_port) = self.gdb_port {
+            ini.with_section(Some("General")).set("gdb_port", gdb_port.to_string());
+        }
+        ini.with_section(Some("General")).set("instructions_per_frame", self.instructions_per_frame.to_string());
+        if let Some(seed) = self.seed {
+            ini.with_section(Some("General")).set("seed", seed.to_string());
+        }
+        ini.with_section(Some("General")).set("random", self.random.get_name());
+        if let Some(vip_interpreter) = &self.vip_interpreter {
+            ini.with_section(Some("General")).set("vip_interpreter", vip_interpreter);
+        }
+        if let Some(stack_limit) = self.stack_limit {
+            ini.with_section(Some("General")).set("stack_limit", stack_limit.to_string());
+        }
         ini.with_section(Some("Colors"))
             .set("on_color", format!("{:#04x}", self.on_color))
-            .set("off_color", format!("{:#04x}", self.off_color));
+            .set("off_color", format!("{:#04x}", self.off_color))
+            .set("plane2_color", format!("{:#04x}", self.plane2_color))
+            .set("overlap_color", format!("{:#04x}", self.overlap_color));
+        ini.with_section(Some("Audio"))
+            .set("frequency", self.tone.frequency.to_string())
+            .set("volume", self.tone.volume.to_string())
+            .set("waveform", self.tone.waveform.get_name());
+        if let Some(wav_output) = &self.wav_output {
+            ini.with_section(Some("Audio")).set("wav_output", wav_output);
+        }
+        ini.with_section(Some("Rewind"))
+            .set("rewind_seconds", self.rewind_seconds.to_string())
+            .set("rewind_memory_mb", self.rewind_memory_mb.to_string());
+        if let Some(trace_output) = &self.trace_output {
+            ini.with_section(Some("Trace"))
+                .set("trace_output", trace_output)
+                .set("trace_format", self.trace_format.get_name());
+            if !self.trace_filter.pc_ranges.is_empty() {
+                ini.with_section(Some("Trace")).set("trace_pc", self.trace_filter.format_pc_ranges());
+            }
+            if !self.trace_filter.opcode_classes.is_empty() {
+                ini.with_section(Some("Trace")).set("trace_opcodes", self.trace_filter.format_opcode_classes());
+            }
+            if let Some(max_cycles) = self.trace_filter.max_cycles {
+                ini.with_section(Some("Trace")).set("trace_cycles", max_cycles.to_string());
+            }
+        }
+        Self::write_quirks(&mut ini, &self.quirks);
         ini.write_to_file("config.ini")?;
         Ok(())
     }
 
+    /// Reads the quirks written by `write_quirks`. Without a profile the
+    /// quirks follow the selected mode.
+    fn read_quirks(ini: &Ini, mode: ProcessorMode) -> Quirks {
+        let mut quirks = QuirksProfile::for_mode(mode).quirks();
+        for (_sec, prop) in ini {
+            for (key, value) in prop.iter() {
+                match key {
+                    "profile" => match QuirksProfile::from_name(value) {
+                        Some(profile) => quirks = profile.quirks(),
+                        None if value == "custom" => {}
+                        None => println!("ERROR Unknown quirks profile {value}"),
+                    },
+                    "shift" => quirks.shift = value == "true",
+                    "memory_increment" => quirks.memory_increment = value == "true",
+                    "vf_reset" => quirks.vf_reset = value == "true",
+                    "jump_with_vx" => quirks.jump_with_vx = value == "true",
+                    "clipping" => quirks.clipping = value == "true",
+                    "display_wait" => quirks.display_wait = value == "true",
+                    "memory_increment_by_x" => quirks.memory_increment_by_x = value == "true",
+                    "index_overflow" => quirks.index_overflow = value == "true",
+                    _ => {}
+                }
+            }
+        }
+        quirks
+    }
+
+    fn write_quirks(ini: &mut Ini, quirks: &Quirks) {
+        let profile = match QuirksProfile::matching(quirks) {
+            Some(profile) => profile.get_name(),
+            None => "custom",
+        };
+        ini.with_section(Some("Quirks"))
+            .set("profile", profile)
+            .set("shift", quirks.shift.to_string())
+            .set("memory_increment", quirks.memory_increment.to_string())
+            .set("vf_reset", quirks.vf_reset.to_string())
+            .set("jump_with_vx", quirks.jump_with_vx.to_string())
+            .set("clipping", quirks.clipping.to_string())
+            .set("display_wait", quirks.display_wait.to_string())
+            .set("memory_increment_by_x", quirks.memory_increment_by_x.to_string())
+            .set("index_overflow", quirks.index_overflow.to_string());
+    }
+
+    /// The display colors indexed by XO-CHIP plane bitmask.
+    pub fn get_colors(&self) -> [u32; 4] {
+        [self.off_color, self.on_color, self.plane2_color, self.overlap_color]
+    }
+
     pub fn get_palettes(&self) -> &Vec<Palette> {
         &self.palettes
     }
@@ -162,3 +434,39 @@ impl Palette {
         (self.on_color, self.off_color)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(quirks: &Quirks, mode: ProcessorMode) -> Quirks {
+        let mut ini = Ini::new();
+        Config::write_quirks(&mut ini, quirks);
+        let mut text = Vec::new();
+        ini.write_to(&mut text).unwrap();
+        Config::read_quirks(&Ini::load_from_str(&String::from_utf8(text).unwrap()).unwrap(), mode)
+    }
+
+    #[test]
+    fn quirks_round_trip() {
+        for profile in QuirksProfile::ALL {
+            assert_eq!(round_trip(&profile.quirks(), ProcessorMode::Chip8), profile.quirks());
+        }
+        let custom = Quirks { jump_with_vx: true, ..QuirksProfile::XOChip.quirks() };
+        assert_eq!(round_trip(&custom, ProcessorMode::Chip8), custom);
+    }
+
+    #[test]
+    fn quirks_follow_profile_and_mode() {
+        let ini = Ini::load_from_str("[General]\nmode=superchip\n").unwrap();
+        assert_eq!(Config::read_quirks(&ini, ProcessorMode::SuperChip), QuirksProfile::SuperChip11.quirks());
+
+        // Individual quirks override the profile
+        let ini = Ini::load_from_str("[Quirks]\nprofile=cosmac-vip\nshift=true\n").unwrap();
+        let expected = Quirks { shift: true, ..QuirksProfile::CosmacVip.quirks() };
+        assert_eq!(Config::read_quirks(&ini, ProcessorMode::XOChip), expected);
+
+        let ini = Ini::load_from_str("[Quirks]\nprofile=unknown\n").unwrap();
+        assert_eq!(Config::read_quirks(&ini, ProcessorMode::XOChip), QuirksProfile::XOChip.quirks());
+    }
+}
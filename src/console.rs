@@ -0,0 +1,263 @@
+use crate::debugger::{self, Breakpoint, Condition, Debugger};
+use crate::dump;
+use crate::emulator::Emulator;
+use crate::instruction::Instruction;
+use crate::processor::Processor;
+use std::fmt::Write;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const HELP: &str = "Commands:
+  c, continue            Resume the program
+  p, pause               Pause the program
+  s, step [n]            Execute n instructions (default 1)
+  n, next                Step over a call
+  finish                 Run until the current subroutine returns
+  b <addr> [if <cond>]   Break at an address, only while the condition holds
+  b if <cond>            Break as soon as the condition holds, e.g. b if v3 == 0x10
+  w <addr>               Break when the byte at an address changes
+  d <n>                  Delete breakpoint or watchpoint n
+  info                   List breakpoints and watchpoints
+  regs                   Show the registers
+  x/<n> <addr>           Show n bytes of memory (default 16)
+";
+
+/// Reads debugger commands from stdin on a separate thread, so they can be
+/// typed while the window keeps running.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Console {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Console { lines }
+    }
+
+    /// Executes the commands typed since the last call and prints their output.
+    pub fn poll(&mut self, emulator: &mut Emulator) {
+        while let Ok(line) = self.lines.try_recv() {
+            print!("{}", execute(emulator, &line));
+        }
+    }
+}
+
+/// Executes one console command and returns its output. Adds a debugger to
+/// the emulator if it has none yet.
+pub fn execute(emulator: &mut Emulator, line: &str) -> String {
+    if emulator.get_debugger().is_none() {
+        emulator.set_debugger(Some(Debugger::new()));
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (command, args) = match words.split_first() {
+        Some((command, args)) => (*command, args),
+        None => return String::new(),
+    };
+
+    match command {
+        "c" | "continue" => {
+            debugger_mut(emulator).resume();
+            String::from("Continuing\n")
+        }
+        "p" | "pause" => {
+            debugger_mut(emulator).pause();
+            location(emulator.get_processor())
+        }
+        "s" | "step" => match args.first().map_or(Some(1), |count| debugger::parse_number(count)) {
+            Some(count) => step(emulator, count),
+            None => String::from("ERROR Expected s [n]\n"),
+        },
+        "n" | "next" => {
+            let pc = emulator.get_processor().get_pc();
+            if emulator.step_over() {
+                format!("Stepping over the call at {pc:#06x}\n")
+            } else {
+                step(emulator, 1)
+            }
+        }
+        "finish" => {
+            if emulator.run_to_return() {
+                String::from("Running until the subroutine returns\n")
+            } else {
+                String::from("ERROR The program is not in a subroutine\n")
+            }
+        }
+        "b" | "break" => match parse_breakpoint(args) {
+            Some(breakpoint) => {
+                let number = debugger_mut(emulator).add_breakpoint(breakpoint);
+                format!("Breakpoint {number} {breakpoint}\n")
+            }
+            None => String::from("ERROR Expected b <addr> [if <cond>] or b if <cond>\n"),
+        },
+        "w" | "watch" => match args {
+            [address] => match debugger::parse_number(address) {
+                Some(address) if address < emulator.get_processor().get_ram().len() => {
+                    let number = debugger_mut(emulator).add_watchpoint(address);
+                    format!("Watchpoint {number} at {address:#06x}\n")
+                }
+                _ => format!("ERROR Invalid address {address}\n"),
+            },
+            _ => String::from("ERROR Expected w <addr>\n"),
+        },
+        "d" | "delete" => match args.first().and_then(|number| number.parse().ok()) {
+            Some(number) if debugger_mut(emulator).remove(number) => format!("Deleted {number}\n"),
+            Some(number) => format!("ERROR No breakpoint or watchpoint {number}\n"),
+            None => String::from("ERROR Expected d <n>\n"),
+        },
+        "info" => {
+            let debugger = emulator.get_debugger().unwrap();
+            let mut output = String::new();
+            for (number, breakpoint) in debugger.get_breakpoints() {
+                writeln!(output, "Breakpoint {number} {breakpoint}").unwrap();
+            }
+            for (number, address) in debugger.get_watchpoints() {
+                writeln!(output, "Watchpoint {number} at {address:#06x}").unwrap();
+            }
+            if output.is_empty() {
+                output.push_str("No breakpoints or watchpoints\n");
+            }
+            output
+        }
+        "regs" => dump::registers(emulator.get_processor()),
+        "h" | "help" => String::from(HELP),
+        _ if command == "x" || command.starts_with("x/") => {
+            let count = match command.strip_prefix("x/") {
+                Some(count) => debugger::parse_number(count),
+                None => Some(16),
+            };
+            match (count, args.first().and_then(|address| debugger::parse_number(address))) {
+                (Some(count), Some(address)) => memory(emulator.get_processor(), address, count),
+                _ => String::from("ERROR Expected x/<n> <addr>\n"),
+            }
+        }
+        _ => format!("ERROR Unknown command {command}, try help\n"),
+    }
+}
+
+fn debugger_mut(emulator: &mut Emulator) -> &mut Debugger {
+    emulator.get_debugger_mut().unwrap()
+}
+
+/// Pauses the program and executes `count` instructions, stopping early on a
+/// fault or a watchpoint.
+fn step(emulator: &mut Emulator, count: usize) -> String {
+    debugger_mut(emulator).pause();
+
+    let mut output = String::new();
+    for _ in 0..count {
+        if emulator.is_halted() {
+            output.push_str("ERROR The program is halted\n");
+            break;
+        }
+        let result = emulator.step_instruction();
+        if let Some(e) = result.error {
+            writeln!(output, "ERROR Program halted: {e}").unwrap();
+            break;
+        }
+        if let Some(reason) = result.stop {
+            writeln!(output, "Stopped: {reason}").unwrap();
+            break;
+        }
+    }
+    output.push_str(&location(emulator.get_processor()));
+    output
+}
+
+/// Parses `<addr> [if <cond>]` or `if <cond>`.
+fn parse_breakpoint(args: &[&str]) -> Option<Breakpoint> {
+    let (address, rest) = match args {
+        ["if", rest @ ..] => (None, rest),
+        [address, rest @ ..] => (Some(debugger::parse_number(address)?), rest),
+        [] => return None,
+    };
+    let condition = match rest {
+        [] => None,
+        ["if", operand, comparison, value] => Some(Condition::parse(operand, comparison, value)?),
+        _ => return None,
+    };
+    if address.is_none() && condition.is_none() {
+        return None;
+    }
+    Some(Breakpoint { address, condition })
+}
+
+/// Shows `count` bytes from `address` on, 16 per line.
+fn memory(processor: &Processor, address: usize, count: usize) -> String {
+    let ram = processor.get_ram();
+    let end = address.saturating_add(count).min(ram.len());
+    if address >= end {
+        return format!("ERROR Address {address:#06x} is outside memory\n");
+    }
+
+    let mut output = String::new();
+    for (row, bytes) in ram[address..end].chunks(16).enumerate() {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        writeln!(output, "{:#06x}: {}", address + row * 16, bytes.join(" ")).unwrap();
+    }
+    output
+}
+
+/// Describes where the program is, with the next instruction.
+pub fn location(processor: &Processor) -> String {
+    let pc = processor.get_pc();
+    let ram = processor.get_ram();
+    match (ram.get(pc), ram.get(pc + 1)) {
+        (Some(high), Some(low)) => {
+            let opcode = (*high as u16) << 8 | *low as u16;
+            match Instruction::decode(opcode) {
+                Some(instruction) => format!("{pc:#06x}: {opcode:04x}  {instruction}\n"),
+                None => format!("{pc:#06x}: {opcode:04x}  ???\n"),
+            }
+        }
+        _ => format!("{pc:#06x}: outside memory\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+
+    fn create_emulator() -> Emulator {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v0 := 0x12, i := 0x300, save v0, jump to self
+        processor.load_data(&[0x60, 0x12, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]).unwrap();
+        Emulator::new(processor)
+    }
+
+    #[test]
+    fn steps_and_shows_state() {
+        let mut emulator = create_emulator();
+        assert_eq!(execute(&mut emulator, "s 2"), "0x0204: f055  LD [I], V0\n");
+        assert!(execute(&mut emulator, "regs").contains("V0=12"));
+        execute(&mut emulator, "s");
+        assert_eq!(execute(&mut emulator, "x/4 0x2ff"), "0x02ff: 00 12 00 00\n");
+        assert!(execute(&mut emulator, "bogus").starts_with("ERROR"));
+    }
+
+    #[test]
+    fn sets_breakpoints() {
+        let mut emulator = create_emulator();
+        assert_eq!(execute(&mut emulator, "b 0x204 if v0 == 0x12"), "Breakpoint 1 at 0x0204 if V0 == 0x12\n");
+        assert_eq!(execute(&mut emulator, "w 0x300"), "Watchpoint 2 at 0x0300\n");
+        assert!(execute(&mut emulator, "b if").starts_with("ERROR"));
+
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(result.stop, Some(debugger::StopReason::Breakpoint(1)));
+        execute(&mut emulator, "c");
+        let result = emulator.run_frame(&[false; 16]);
+        assert!(matches!(result.stop, Some(debugger::StopReason::Watchpoint { address: 0x300, .. })));
+
+        assert_eq!(execute(&mut emulator, "d 1"), "Deleted 1\n");
+        assert_eq!(execute(&mut emulator, "info"), "Watchpoint 2 at 0x0300\n");
+    }
+}
@@ -0,0 +1,360 @@
+use crate::instruction::Instruction;
+use crate::processor::Processor;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A value of the processor a breakpoint condition looks at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operand {
+    Register(u8),
+    Index,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Compares a register or the index register with a constant.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    /// Parses conditions like `v3 == 0x10` or `i >= 0x300`.
+    pub fn parse(operand: &str, comparison: &str, value: &str) -> Option<Condition> {
+        let operand = match operand.to_ascii_lowercase().as_str() {
+            "i" => Operand::Index,
+            name => {
+                let register = name.strip_prefix('v')?;
+                if register.len() != 1 {
+                    return None;
+                }
+                Operand::Register(u8::from_str_radix(register, 16).ok()?)
+            }
+        };
+        let comparison = match comparison {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        };
+        let value = parse_number(value)? as u16;
+        Some(Condition { operand, comparison, value })
+    }
+
+    pub fn matches(&self, processor: &Processor) -> bool {
+        let actual = match self.operand {
+            Operand::Register(x) => processor.get_registers()[x as usize] as u16,
+            Operand::Index => processor.get_index() as u16,
+        };
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::Greater => actual > self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operand {
+            Operand::Register(x) => write!(f, "V{x:X}")?,
+            Operand::Index => write!(f, "I")?,
+        }
+        let comparison = match self.comparison {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, " {comparison} {:#x}", self.value)
+    }
+}
+
+/// Stops the program before the instruction at `address` runs, if the
+/// condition holds. Without an address the condition is checked before every
+/// instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Breakpoint {
+    pub address: Option<usize>,
+    pub condition: Option<Condition>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.address, &self.condition) {
+            (Some(address), Some(condition)) => write!(f, "at {address:#06x} if {condition}"),
+            (Some(address), None) => write!(f, "at {address:#06x}"),
+            (None, Some(condition)) => write!(f, "if {condition}"),
+            (None, None) => write!(f, "everywhere"),
+        }
+    }
+}
+
+/// Why the debugger paused the program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    /// Paused from the console.
+    Paused,
+    /// The breakpoint with this number was hit.
+    Breakpoint(usize),
+    /// The watched byte at `address` was changed.
+    Watchpoint { address: usize, old: u8, new: u8 },
+    /// A step over a call or a run to the end of a subroutine finished.
+    Stepped,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Paused => write!(f, "paused"),
+            StopReason::Breakpoint(number) => write!(f, "hit breakpoint {number}"),
+            StopReason::Watchpoint { address, old, new } => {
+                write!(f, "watched byte {address:#06x} changed from {old:#04x} to {new:#04x}")
+            }
+            StopReason::Stepped => write!(f, "stepped"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RunMode {
+    Running,
+    Paused,
+    /// Runs until the program is back at `address` with no more than `depth`
+    /// return addresses on the stack.
+    StepOver { address: usize, depth: usize },
+    /// Runs until the stack holds fewer than `depth` return addresses.
+    RunToReturn { depth: usize },
+}
+
+/// Breakpoints, watchpoints and the pause state of a running program. The
+/// emulator asks it before and after every instruction whether to stop.
+pub struct Debugger {
+    mode: RunMode,
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    // Maps watched addresses to their number and last seen value
+    watchpoints: BTreeMap<usize, (usize, u8)>,
+    next_number: usize,
+    // Set when the program resumes, so it doesn't stop at the breakpoint it
+    // was paused at
+    resumed: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            mode: RunMode::Running,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_number: 1,
+            resumed: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = RunMode::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = RunMode::Running;
+        self.resumed = true;
+    }
+
+    /// Runs until the subroutine called by the instruction at the program
+    /// counter returns. Returns false, leaving the mode alone, if that
+    /// instruction isn't a call.
+    pub fn step_over(&mut self, processor: &Processor) -> bool {
+        let pc = processor.get_pc();
+        let ram = processor.get_ram();
+        let opcode = match (ram.get(pc), ram.get(pc + 1)) {
+            (Some(high), Some(low)) => (*high as u16) << 8 | *low as u16,
+            _ => return false,
+        };
+        if !matches!(Instruction::decode(opcode), Some(Instruction::Call(_))) {
+            return false;
+        }
+        self.mode = RunMode::StepOver { address: pc + 2, depth: processor.get_stack().len() };
+        self.resumed = true;
+        true
+    }
+
+    /// Runs until the current subroutine returns. Returns false if the
+    /// program isn't in a subroutine.
+    pub fn run_to_return(&mut self, processor: &Processor) -> bool {
+        let depth = processor.get_stack().len();
+        if depth == 0 {
+            return false;
+        }
+        self.mode = RunMode::RunToReturn { depth };
+        self.resumed = true;
+        true
+    }
+
+    /// Adds a breakpoint and returns its number.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        self.breakpoints.insert(number, breakpoint);
+        number
+    }
+
+    /// Watches the byte at `address` for changes and returns the number of
+    /// the watchpoint. Its value is taken when the program next runs.
+    pub fn add_watchpoint(&mut self, address: usize) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        self.watchpoints.insert(address, (number, 0));
+        number
+    }
+
+    /// Removes the breakpoint or watchpoint with this number.
+    pub fn remove(&mut self, number: usize) -> bool {
+        if self.breakpoints.remove(&number).is_some() {
+            return true;
+        }
+        let address = self.watchpoints.iter().find(|(_, (n, _))| *n == number).map(|(address, _)| *address);
+        match address {
+            Some(address) => self.watchpoints.remove(&address).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn get_breakpoints(&self) -> &BTreeMap<usize, Breakpoint> {
+        &self.breakpoints
+    }
+
+    /// The watched addresses and the numbers of their watchpoints.
+    pub fn get_watchpoints(&self) -> Vec<(usize, usize)> {
+        self.watchpoints.iter().map(|(address, (number, _))| (*number, *address)).collect()
+    }
+
+    /// Remembers the current value of every watched byte, so changes made
+    /// outside of the program, like loading a state, don't trigger them.
+    pub fn sync_watchpoints(&mut self, processor: &Processor) {
+        let ram = processor.get_ram();
+        for (address, (_, value)) in self.watchpoints.iter_mut() {
+            *value = ram.get(*address).copied().unwrap_or(0);
+        }
+    }
+
+    /// Called before every instruction, pauses and returns the reason if the
+    /// program should stop before executing it.
+    pub fn check_before(&mut self, processor: &Processor) -> Option<StopReason> {
+        let resumed = std::mem::take(&mut self.resumed);
+
+        let reason = match self.mode {
+            RunMode::Paused => Some(StopReason::Paused),
+            RunMode::StepOver { address, depth }
+                if processor.get_pc() == address && processor.get_stack().len() <= depth =>
+            {
+                Some(StopReason::Stepped)
+            }
+            RunMode::RunToReturn { depth } if processor.get_stack().len() < depth => Some(StopReason::Stepped),
+            _ if resumed => None,
+            _ => self
+                .breakpoints
+                .iter()
+                .find(|(_, breakpoint)| {
+                    breakpoint.address.is_none_or(|address| address == processor.get_pc())
+                        && breakpoint.condition.is_none_or(|condition| condition.matches(processor))
+                })
+                .map(|(number, _)| StopReason::Breakpoint(*number)),
+        };
+        if reason.is_some() {
+            self.mode = RunMode::Paused;
+        }
+        reason
+    }
+
+    /// Called after every instruction, pauses and returns the reason if it
+    /// changed a watched byte.
+    pub fn check_after(&mut self, processor: &Processor) -> Option<StopReason> {
+        let ram = processor.get_ram();
+        let mut reason = None;
+        for (address, (_, value)) in self.watchpoints.iter_mut() {
+            let new = ram.get(*address).copied().unwrap_or(0);
+            if new != *value && reason.is_none() {
+                reason = Some(StopReason::Watchpoint { address: *address, old: *value, new });
+            }
+            *value = new;
+        }
+        if reason.is_some() {
+            self.mode = RunMode::Paused;
+        }
+        reason
+    }
+}
+
+/// Parses a number given in hex with a 0x prefix or in decimal.
+pub fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+
+    #[test]
+    fn parses_conditions() {
+        let condition = Condition::parse("vA", ">=", "0x10").unwrap();
+        assert_eq!(condition.operand, Operand::Register(0xA));
+        assert_eq!(condition.comparison, Comparison::GreaterOrEqual);
+        assert_eq!(condition.value, 0x10);
+        assert_eq!(condition.to_string(), "VA >= 0x10");
+        assert!(Condition::parse("v10", "==", "1").is_none());
+        assert!(Condition::parse("i", "=", "1").is_none());
+    }
+
+    #[test]
+    fn stops_on_condition() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v3 += 1, jump back
+        processor.load_data(&[0x73, 0x01, 0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+        let condition = Condition::parse("v3", "==", "2");
+        debugger.add_breakpoint(Breakpoint { address: Some(0x200), condition });
+
+        let mut executed = 0;
+        while debugger.check_before(&processor).is_none() {
+            processor.step().unwrap();
+            executed += 1;
+        }
+        assert_eq!(executed, 4);
+        assert!(debugger.is_paused());
+
+        debugger.resume();
+        assert_eq!(debugger.check_before(&processor), None);
+    }
+}
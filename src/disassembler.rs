@@ -0,0 +1,233 @@
+use crate::config::ProcessorMode;
+use crate::instruction::Instruction;
+
+/// Programs are loaded at this address, so it is where execution starts.
+pub const PROGRAM_START: usize = 0x200;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Syntax {
+    /// The assembly language of the Octo IDE.
+    Octo,
+    /// The mnemonics of Cowgod's Chip-8 technical reference.
+    Cowgod,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            _ => None,
+        }
+    }
+}
+
+/// One line of disassembly: either a single instruction or a run of data bytes.
+pub struct Line {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+/// Marks every byte of `rom` that is reachable as code by following the
+/// control flow from the program start. `rom` is the program as loaded at
+/// `PROGRAM_START`.
+pub fn find_code(rom: &[u8], mode: ProcessorMode) -> Vec<bool> {
+    let mut is_code = vec![false; rom.len()];
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        let instruction = match decode_at(rom, address, mode) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let offset = address - PROGRAM_START;
+        if is_code[offset] {
+            continue;
+        }
+        let length = instruction.get_length();
+        is_code[offset..offset + length].fill(true);
+
+        let next = address + length;
+        match instruction {
+            Instruction::Jump(nnn) => pending.push(nnn as usize),
+            Instruction::Call(nnn) => {
+                pending.push(nnn as usize);
+                pending.push(next);
+            }
+            // The jump target depends on a register, so it can't be followed
+            Instruction::Return | Instruction::Exit | Instruction::JumpWithOffset(_) => {}
+            Instruction::SkipIfEqualImmediate(..)
+            | Instruction::SkipIfNotEqualImmediate(..)
+            | Instruction::SkipIfEqual(..)
+            | Instruction::SkipIfNotEqual(..)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_) => {
+                pending.push(next);
+                if let Some(skipped) = decode_at(rom, next, mode) {
+                    pending.push(next + skipped.get_length());
+                }
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    is_code
+}
+
+/// Disassembles `rom`, printing the bytes that are never reached as data.
+pub fn disassemble(rom: &[u8], mode: ProcessorMode, syntax: Syntax) -> Vec<Line> {
+    let is_code = find_code(rom, mode);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset;
+
+        let instruction = if is_code[offset] { decode_at(rom, address, mode) } else { None };
+
+        if let Some(instruction) = instruction {
+            let length = instruction.get_length();
+            let long_address = if length == 4 { word_at(rom, address + 2).unwrap() } else { 0 };
+            let text = match syntax {
+                Syntax::Octo => format_octo(&instruction, long_address),
+                Syntax::Cowgod if length == 4 => format!("{instruction} {long_address:#06x}"),
+                Syntax::Cowgod => instruction.to_string(),
+            };
+            lines.push(Line { address, bytes: rom[offset..offset + length].to_vec(), text });
+            offset += length;
+        } else {
+            let mut end = offset + 1;
+            while end < rom.len() && !is_code[end] && end - offset < DATA_BYTES_PER_LINE {
+                end += 1;
+            }
+            let bytes = rom[offset..end].to_vec();
+            let values: Vec<String> = bytes.iter().map(|byte| format!("{byte:#04x}")).collect();
+            let text = match syntax {
+                Syntax::Octo => values.join(" "),
+                Syntax::Cowgod => format!("DB {}", values.join(", ")),
+            };
+            lines.push(Line { address, bytes, text });
+            offset = end;
+        }
+    }
+
+    lines
+}
+
+/// Formats an instruction in Octo syntax. `long_address` is the operand of
+/// F000 NNNN and ignored for every other instruction.
+pub fn format_octo(instruction: &Instruction, long_address: u16) -> String {
+    match *instruction {
+        Instruction::ScrollDown(n) => format!("scroll-down {n}"),
+        Instruction::ScrollUp(n) => format!("scroll-up {n}"),
+        Instruction::ClearScreen => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LoRes => "lores".to_string(),
+        Instruction::HiRes => "hires".to_string(),
+        Instruction::Jump(nnn) => format!("jump {nnn:#05x}"),
+        Instruction::Call(nnn) => format!(":call {nnn:#05x}"),
+        // Octo skips the next instruction when its condition is false
+        Instruction::SkipIfEqualImmediate(x, nn) => format!("if v{x:x} != {nn:#04x} then"),
+        Instruction::SkipIfNotEqualImmediate(x, nn) => format!("if v{x:x} == {nn:#04x} then"),
+        Instruction::SkipIfEqual(x, y) => format!("if v{x:x} != v{y:x} then"),
+        Instruction::SaveRange(x, y) => format!("save v{x:x} - v{y:x}"),
+        Instruction::LoadRange(x, y) => format!("load v{x:x} - v{y:x}"),
+        Instruction::SetImmediate(x, nn) => format!("v{x:x} := {nn:#04x}"),
+        Instruction::AddImmediate(x, nn) => format!("v{x:x} += {nn:#04x}"),
+        Instruction::Set(x, y) => format!("v{x:x} := v{y:x}"),
+        Instruction::Or(x, y) => format!("v{x:x} |= v{y:x}"),
+        Instruction::And(x, y) => format!("v{x:x} &= v{y:x}"),
+        Instruction::Xor(x, y) => format!("v{x:x} ^= v{y:x}"),
+        Instruction::Add(x, y) => format!("v{x:x} += v{y:x}"),
+        Instruction::Sub(x, y) => format!("v{x:x} -= v{y:x}"),
+        Instruction::ShiftRight(x, y) => format!("v{x:x} >>= v{y:x}"),
+        Instruction::SubReverse(x, y) => format!("v{x:x} =- v{y:x}"),
+        Instruction::ShiftLeft(x, y) => format!("v{x:x} <<= v{y:x}"),
+        Instruction::SkipIfNotEqual(x, y) => format!("if v{x:x} == v{y:x} then"),
+        Instruction::SetIndex(nnn) => format!("i := {nnn:#05x}"),
+        Instruction::JumpWithOffset(nnn) => format!("jump0 {nnn:#05x}"),
+        Instruction::Random(x, nn) => format!("v{x:x} := random {nn:#04x}"),
+        Instruction::Draw(x, y, n) => format!("sprite v{x:x} v{y:x} {n}"),
+        Instruction::SkipIfKey(x) => format!("if v{x:x} -key then"),
+        Instruction::SkipIfNotKey(x) => format!("if v{x:x} key then"),
+        Instruction::LongIndex => format!("i := long {long_address:#06x}"),
+        Instruction::SelectPlanes(n) => format!("plane {n}"),
+        Instruction::LoadAudio => "audio".to_string(),
+        Instruction::GetDelay(x) => format!("v{x:x} := delay"),
+        Instruction::WaitKey(x) => format!("v{x:x} := key"),
+        Instruction::SetDelay(x) => format!("delay := v{x:x}"),
+        Instruction::SetSound(x) => format!("buzzer := v{x:x}"),
+        Instruction::AddIndex(x) => format!("i += v{x:x}"),
+        Instruction::Font(x) => format!("i := hex v{x:x}"),
+        Instruction::BigFont(x) => format!("i := bighex v{x:x}"),
+        Instruction::Bcd(x) => format!("bcd v{x:x}"),
+        Instruction::SetPitch(x) => format!("pitch := v{x:x}"),
+        Instruction::Store(x) => format!("save v{x:x}"),
+        Instruction::Load(x) => format!("load v{x:x}"),
+        Instruction::SaveFlags(x) => format!("saveflags v{x:x}"),
+        Instruction::LoadFlags(x) => format!("loadflags v{x:x}"),
+    }
+}
+
+fn word_at(rom: &[u8], address: usize) -> Option<u16> {
+    let offset = address.checked_sub(PROGRAM_START)?;
+    let high = *rom.get(offset)?;
+    let low = *rom.get(offset + 1)?;
+    Some((high as u16) << 8 | low as u16)
+}
+
+/// Decodes the instruction at `address` if it lies completely inside the rom
+/// and `mode` supports it.
+fn decode_at(rom: &[u8], address: usize, mode: ProcessorMode) -> Option<Instruction> {
+    let instruction = Instruction::decode(word_at(rom, address)?)?;
+    if !instruction.is_supported(mode) {
+        return None;
+    }
+    if instruction.get_length() == 4 {
+        word_at(rom, address + 2)?;
+    }
+    Some(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_after_jump() {
+        let rom = [0x60, 0x01, 0x12, 0x00, 0xFF, 0x81];
+        let is_code = find_code(&rom, ProcessorMode::Chip8);
+        assert_eq!(is_code, vec![true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn follows_skips_and_calls() {
+        let rom = [0x30, 0x01, 0x22, 0x08, 0x12, 0x06, 0x12, 0x06, 0x00, 0xEE, 0xAA];
+        let is_code = find_code(&rom, ProcessorMode::Chip8);
+        assert!(is_code[..10].iter().all(|code| *code));
+        assert!(!is_code[10]);
+    }
+
+    #[test]
+    fn long_index_is_four_bytes() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let lines = disassemble(&rom, ProcessorMode::XOChip, Syntax::Octo);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "i := long 0x1234");
+        assert_eq!(lines[1].address, 0x204);
+    }
+
+    #[test]
+    fn data_lines() {
+        let rom = [0x00, 0xEE, 0x01, 0x02];
+        let lines = disassemble(&rom, ProcessorMode::Chip8, Syntax::Cowgod);
+        assert_eq!(lines[0].text, "RET");
+        assert_eq!(lines[1].text, "DB 0x01, 0x02");
+    }
+}
@@ -0,0 +1,73 @@
+use crate::processor::Processor;
+use std::fmt::Write;
+
+/// Characters used for a pixel in the ASCII dump, indexed by plane bitmask.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// Renders the framebuffer as one line of text per row.
+pub fn framebuffer_to_ascii(framebuffer: &[Vec<u8>]) -> String {
+    let mut ascii = String::new();
+    for row in framebuffer {
+        for pixel in row {
+            ascii.push(ASCII_PIXELS[(*pixel & 0x3) as usize]);
+        }
+        ascii.push('\n');
+    }
+    ascii
+}
+
+/// Renders the framebuffer as a plain PBM (P1) image, where a pixel is
+/// black if it is lit on any plane.
+pub fn framebuffer_to_pbm(framebuffer: &[Vec<u8>]) -> String {
+    let height = framebuffer.len();
+    let width = framebuffer.first().map_or(0, |row| row.len());
+
+    let mut pbm = format!("P1\n{width} {height}\n");
+    for row in framebuffer {
+        let pixels: Vec<&str> = row.iter().map(|pixel| if *pixel != 0 { "1" } else { "0" }).collect();
+        pbm.push_str(&pixels.join(" "));
+        pbm.push('\n');
+    }
+    pbm
+}
+
+/// Describes the registers, timers and stack of the processor.
+pub fn registers(processor: &Processor) -> String {
+    let mut text = String::new();
+
+    writeln!(
+        text,
+        "PC={:#06x} I={:#06x} SP={} DT={} ST={}",
+        processor.get_pc(),
+        processor.get_index(),
+        processor.get_stack().len(),
+        processor.get_delay_timer(),
+        processor.get_sound_timer()
+    )
+    .unwrap();
+
+    let registers: Vec<String> = processor
+        .get_registers()
+        .iter()
+        .enumerate()
+        .map(|(i, value)| format!("V{i:X}={value:02X}"))
+        .collect();
+    writeln!(text, "{}", registers.join(" ")).unwrap();
+
+    let stack: Vec<String> = processor.get_stack().iter().map(|address| format!("{address:#06x}")).collect();
+    writeln!(text, "Stack: [{}]", stack.join(", ")).unwrap();
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_and_pbm() {
+        let framebuffer = vec![vec![0, 1, 2], vec![3, 0, 0]];
+        assert_eq!(framebuffer_to_ascii(&framebuffer), ".#+\n@..\n");
+        assert_eq!(framebuffer_to_pbm(&framebuffer), "P1\n3 2\n0 1 1\n1 0 0\n");
+    }
+}
@@ -0,0 +1,463 @@
+use crate::audio::Pattern;
+use crate::debugger::{Debugger, StopReason};
+use crate::frontend::{AudioSink, InputSource, VideoSink};
+use crate::processor::{ExecError, Processor, StepOutcome};
+use crate::rewind::Rewind;
+use crate::state::StateError;
+use crate::trace::Tracer;
+
+/// The default speed, about 900 instructions per second at 60 frames per second.
+pub const INSTRUCTIONS_PER_FRAME: u32 = 15;
+
+/// What happened during one frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FrameResult {
+    /// The number of instructions executed.
+    pub instructions: u32,
+    /// True if the framebuffer changed.
+    pub vram_changed: bool,
+    /// True while the sound timer runs.
+    pub beeping: bool,
+    /// The XO-CHIP audio pattern, once the program has loaded one.
+    pub pattern: Option<Pattern>,
+    /// True once the program has executed 00FD.
+    pub exited: bool,
+    /// The error that halted the program during this frame.
+    pub error: Option<ExecError>,
+    /// Set when the debugger paused the program during this frame.
+    pub stop: Option<StopReason>,
+}
+
+/// Runs a processor frame by frame with the same timing for every frontend.
+pub struct Emulator {
+    processor: Processor,
+    instructions_per_frame: u32,
+    // Set when the program faults, until the processor is reset or replaced
+    halted: bool,
+    // Set when the frontend has to be sent the framebuffer even if no
+    // instruction changed it
+    redraw: bool,
+    // Set when a draw ended the current frame early
+    frame_done: bool,
+    rewind: Option<Rewind>,
+    debugger: Option<Debugger>,
+    tracer: Option<Tracer>,
+}
+
+impl Emulator {
+    pub fn new(processor: Processor) -> Emulator {
+        Emulator {
+            processor,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            halted: false,
+            redraw: true,
+            frame_done: false,
+            rewind: None,
+            debugger: None,
+            tracer: None,
+        }
+    }
+
+    pub fn get_processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    pub fn get_processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    /// Replaces the processor, for example after switching modes.
+    pub fn set_processor(&mut self, processor: Processor) {
+        self.processor = processor;
+        self.halted = false;
+        self.redraw = true;
+        self.clear_rewind();
+    }
+
+    pub fn get_instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    /// Resets the processor and resumes a halted program.
+    pub fn reset(&mut self) {
+        self.processor.reset();
+        self.halted = false;
+        self.redraw = true;
+        self.clear_rewind();
+    }
+
+    /// Restores a save state, resuming a halted program.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        self.processor.load_state(data)?;
+        self.halted = false;
+        self.redraw = true;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    /// Records a snapshot after every frame, so `rewind` can step back.
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    /// Restores the state of the previous frame. Returns false once there is
+    /// no older frame left.
+    pub fn step_back(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(Rewind::pop) {
+            Some(state) => state,
+            None => return false,
+        };
+        match self.processor.load_state(&state) {
+            Ok(()) => {
+                self.halted = false;
+                self.redraw = true;
+                true
+            }
+            Err(e) => {
+                println!("ERROR Failed to rewind: {e}");
+                self.clear_rewind();
+                false
+            }
+        }
+    }
+
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /// Checks breakpoints and watchpoints around every instruction.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    pub fn get_debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    pub fn get_debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// True while the debugger holds the program.
+    pub fn is_paused(&self) -> bool {
+        self.debugger.as_ref().is_some_and(Debugger::is_paused)
+    }
+
+    /// Logs every executed instruction.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn get_tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Removes the tracer, for handing it to another emulator.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Runs until the call at the program counter returns, see
+    /// `Debugger::step_over`.
+    pub fn step_over(&mut self) -> bool {
+        match &mut self.debugger {
+            Some(debugger) => debugger.step_over(&self.processor),
+            None => false,
+        }
+    }
+
+    /// Runs until the current subroutine returns, see
+    /// `Debugger::run_to_return`.
+    pub fn run_to_return(&mut self) -> bool {
+        match &mut self.debugger {
+            Some(debugger) => debugger.run_to_return(&self.processor),
+            None => false,
+        }
+    }
+
+    /// True once the program has faulted.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Runs one frame: sets the keys, ticks the timers and executes up to
+    /// the configured number of instructions. With the display wait quirk a
+    /// draw ends the frame early.
+    pub fn run_frame(&mut self, keys: &[bool; 16]) -> FrameResult {
+        let mut result = FrameResult::default();
+        // A paused program doesn't see time pass
+        if !self.start_frame(keys) {
+            return result;
+        }
+        while self.step_frame(&mut result) {}
+        self.finish_frame(&mut result);
+        result
+    }
+
+    /// Starts a frame by setting the keys and ticking the timers, for tools
+    /// that look at the processor between the instructions of a frame.
+    /// Returns false, doing nothing, while the debugger holds the program.
+    pub fn start_frame(&mut self, keys: &[bool; 16]) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.sync_watchpoints(&self.processor);
+        }
+
+        self.processor.set_keys(keys);
+        self.processor.update_timers();
+        self.frame_done = false;
+        true
+    }
+
+    /// Executes the next instruction of the frame. Returns false, without
+    /// executing anything, once the frame is over.
+    pub fn step_frame(&mut self, result: &mut FrameResult) -> bool {
+        if self.frame_done
+            || self.halted
+            || result.exited
+            || result.stop.is_some()
+            || result.instructions >= self.instructions_per_frame
+        {
+            return false;
+        }
+        if let Some(debugger) = &mut self.debugger {
+            result.stop = debugger.check_before(&self.processor);
+            if result.stop.is_some() {
+                return false;
+            }
+        }
+        result.instructions += 1;
+        if self.execute(result) && self.processor.get_quirks().display_wait {
+            self.frame_done = true;
+        }
+        true
+    }
+
+    /// Ends the frame begun by `start_frame`.
+    pub fn finish_frame(&mut self, result: &mut FrameResult) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.end_frame();
+        }
+
+        if result.instructions > 0 {
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(self.processor.save_state());
+            }
+        }
+
+        result.beeping = !self.halted && *self.processor.get_sound_timer() > 0;
+        if self.processor.has_audio_pattern() {
+            result.pattern = Some(Pattern {
+                bits: *self.processor.get_audio_pattern(),
+                pitch: self.processor.get_pitch(),
+            });
+        }
+    }
+
+    /// Executes a single instruction outside of the frame timing, for
+    /// stepping through a paused program.
+    pub fn step_instruction(&mut self) -> FrameResult {
+        let mut result = FrameResult::default();
+        if self.halted {
+            return result;
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.sync_watchpoints(&self.processor);
+        }
+        result.instructions = 1;
+        if self.execute(&mut result) {
+            self.redraw = true;
+        }
+        result
+    }
+
+    /// Executes the next instruction into `result`, returning true if it
+    /// changed the framebuffer.
+    fn execute(&mut self, result: &mut FrameResult) -> bool {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.before_step(&self.processor);
+        }
+        let outcome = self.processor.step();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.after_step(&self.processor);
+        }
+        if outcome.is_ok() {
+            if let Some(debugger) = &mut self.debugger {
+                result.stop = debugger.check_after(&self.processor);
+            }
+        }
+        match outcome {
+            Ok(StepOutcome::Executed) => false,
+            Ok(StepOutcome::VramChanged) => {
+                result.vram_changed = true;
+                true
+            }
+            Ok(StepOutcome::Exited) => {
+                result.exited = true;
+                false
+            }
+            Err(e) => {
+                self.halted = true;
+                result.error = Some(e);
+                false
+            }
+        }
+    }
+
+    /// Runs one frame with the keys of `frontend` and presents the result.
+    pub fn update<F>(&mut self, frontend: &mut F) -> FrameResult
+    where
+        F: VideoSink + InputSource + AudioSink,
+    {
+        let result = self.run_frame(&frontend.get_keys());
+        self.present(frontend, &result);
+        result
+    }
+
+    /// Sends the outcome of `run_frame` to the frontend.
+    pub fn present<F>(&mut self, frontend: &mut F, result: &FrameResult)
+    where
+        F: VideoSink + AudioSink,
+    {
+        if result.vram_changed || self.redraw {
+            frontend.set_framebuffer(self.processor.get_framebuffer());
+            self.redraw = false;
+        }
+        frontend.play_frame(result.beeping, result.pattern.as_ref());
+        frontend.refresh_display();
+    }
+
+    /// Plays one frame backwards, silently, and presents the result.
+    pub fn rewind<F>(&mut self, frontend: &mut F)
+    where
+        F: VideoSink + AudioSink,
+    {
+        self.step_back();
+        self.present(frontend, &FrameResult::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+    use crate::debugger::Breakpoint;
+    use crate::frontend::Memory;
+
+    #[test]
+    fn draws_to_frontend() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // i := hex v0, sprite v0 v0 5, jump to self
+        processor.load_data(&[0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04]).unwrap();
+        let mut emulator = Emulator::new(processor);
+        let mut frontend = Memory::new();
+
+        assert!(emulator.update(&mut frontend).error.is_none());
+        assert_eq!(frontend.get_frames(), 1);
+        assert_eq!(frontend.get_framebuffer()[0][..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn halts_on_error() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // Return with an empty stack
+        processor.load_data(&[0x00, 0xEE]).unwrap();
+        let mut emulator = Emulator::new(processor);
+        let mut frontend = Memory::new();
+
+        assert_eq!(emulator.update(&mut frontend).error, Some(ExecError::StackUnderflow));
+        assert!(emulator.is_halted());
+        assert_eq!(emulator.update(&mut frontend).instructions, 0);
+
+        emulator.reset();
+        assert!(!emulator.is_halted());
+    }
+
+    #[test]
+    fn display_wait_ends_frame() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // sprite v0 v0 1, jump back
+        processor.load_data(&[0xD0, 0x01, 0x12, 0x00]).unwrap();
+        let mut emulator = Emulator::new(processor);
+
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(result.instructions, 1);
+        assert!(result.vram_changed);
+
+        let mut quirks = *emulator.get_processor().get_quirks();
+        quirks.display_wait = false;
+        emulator.get_processor_mut().set_quirks(quirks);
+        emulator.set_instructions_per_frame(10);
+        assert_eq!(emulator.run_frame(&[false; 16]).instructions, 10);
+    }
+
+    #[test]
+    fn reports_audio_pattern() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        // i := 0x208, audio, pitch := v0, jump to self, pattern
+        processor.load_data(&[0xA2, 0x08, 0xF0, 0x02, 0xF0, 0x3A, 0x12, 0x06, 0x80]).unwrap();
+        let mut emulator = Emulator::new(processor);
+
+        let pattern = emulator.run_frame(&[false; 16]).pattern.unwrap();
+        assert_eq!(pattern.bits[0], 0x80);
+        assert_eq!(pattern.pitch, 0);
+    }
+
+    #[test]
+    fn steps_back() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v0 += 1, jump back
+        processor.load_data(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut emulator = Emulator::new(processor);
+        emulator.set_instructions_per_frame(2);
+        emulator.set_rewind(Some(Rewind::new(60, 1 << 20)));
+
+        for _ in 0..3 {
+            emulator.run_frame(&[false; 16]);
+        }
+        assert_eq!(emulator.get_processor().get_registers()[0], 3);
+
+        assert!(emulator.step_back());
+        assert_eq!(emulator.get_processor().get_registers()[0], 2);
+        assert!(emulator.step_back());
+        assert_eq!(emulator.get_processor().get_registers()[0], 1);
+        assert!(!emulator.step_back());
+
+        emulator.run_frame(&[false; 16]);
+        assert_eq!(emulator.get_processor().get_registers()[0], 2);
+    }
+
+    #[test]
+    fn debugger_pauses() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // call 0x206, v1 := 5, jump to self, v0 += 1, return
+        processor.load_data(&[0x22, 0x06, 0x61, 0x05, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE]).unwrap();
+        let mut emulator = Emulator::new(processor);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint { address: Some(0x206), condition: None });
+        debugger.add_watchpoint(0x300);
+        emulator.set_debugger(Some(debugger));
+
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(result.stop, Some(StopReason::Breakpoint(1)));
+        assert_eq!(result.instructions, 1);
+        assert!(emulator.is_paused());
+        assert_eq!(emulator.run_frame(&[false; 16]).instructions, 0);
+
+        emulator.step_instruction();
+        assert_eq!(emulator.get_processor().get_registers()[0], 1);
+
+        assert!(emulator.run_to_return());
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(result.stop, Some(StopReason::Stepped));
+        assert_eq!(emulator.get_processor().get_pc(), 0x202);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::audio::Pattern;
+use crate::processor::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Receives the framebuffer of the processor.
+pub trait VideoSink {
+    /// Called with a copy of the framebuffer whenever it changed.
+    fn set_framebuffer(&mut self, framebuffer: &[Vec<u8>]);
+
+    /// Called once at the end of every frame.
+    fn refresh_display(&mut self);
+}
+
+/// Supplies the state of the 16 hex keys.
+pub trait InputSource {
+    /// Called once at the start of every frame.
+    fn get_keys(&mut self) -> [bool; 16];
+}
+
+/// Plays the sound of the processor.
+pub trait AudioSink {
+    /// Called once every frame, `beeping` is true while the sound timer runs.
+    /// `pattern` is set once an XO-CHIP program has loaded an audio pattern.
+    fn play_frame(&mut self, beeping: bool, pattern: Option<&Pattern>);
+}
+
+/// A frontend that shows nothing, plays nothing and never presses a key.
+#[derive(Default)]
+pub struct Null;
+
+impl VideoSink for Null {
+    fn set_framebuffer(&mut self, _framebuffer: &[Vec<u8>]) {}
+
+    fn refresh_display(&mut self) {}
+}
+
+impl InputSource for Null {
+    fn get_keys(&mut self) -> [bool; 16] {
+        [false; 16]
+    }
+}
+
+impl AudioSink for Null {
+    fn play_frame(&mut self, _beeping: bool, _pattern: Option<&Pattern>) {}
+}
+
+/// A frontend that keeps everything in memory, for tests and tools that
+/// embed the emulator.
+pub struct Memory {
+    framebuffer: Vec<Vec<u8>>,
+    keys: [bool; 16],
+    frames: u64,
+    beep_frames: u64,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            framebuffer: vec![vec![0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            keys: [false; 16],
+            frames: 0,
+            beep_frames: 0,
+        }
+    }
+
+    /// Sets the keys reported from the next frame on.
+    pub fn set_keys(&mut self, keys: &[bool; 16]) {
+        self.keys = *keys;
+    }
+
+    /// The last framebuffer received.
+    pub fn get_framebuffer(&self) -> &[Vec<u8>] {
+        &self.framebuffer
+    }
+
+    /// The number of frames displayed.
+    pub fn get_frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The number of frames during which the sound timer was running.
+    pub fn get_beep_frames(&self) -> u64 {
+        self.beep_frames
+    }
+}
+
+impl VideoSink for Memory {
+    fn set_framebuffer(&mut self, framebuffer: &[Vec<u8>]) {
+        self.framebuffer = framebuffer.to_vec();
+    }
+
+    fn refresh_display(&mut self) {
+        self.frames += 1;
+    }
+}
+
+impl InputSource for Memory {
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.keys
+    }
+}
+
+impl AudioSink for Memory {
+    fn play_frame(&mut self, beeping: bool, _pattern: Option<&Pattern>) {
+        if beeping {
+            self.beep_frames += 1;
+        }
+    }
+}
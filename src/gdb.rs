@@ -0,0 +1,642 @@
+use crate::debugger::{Breakpoint, Debugger, StopReason};
+use crate::emulator::{Emulator, FrameResult};
+use crate::processor::{ExecError, Processor};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// The registers in the order of the `g` packet and their size in bits.
+/// Values are sent little endian, the byte order of `ARCHITECTURE`.
+const REGISTERS: [(&str, usize); 21] = [
+    ("v0", 8),
+    ("v1", 8),
+    ("v2", 8),
+    ("v3", 8),
+    ("v4", 8),
+    ("v5", 8),
+    ("v6", 8),
+    ("v7", 8),
+    ("v8", 8),
+    ("v9", 8),
+    ("va", 8),
+    ("vb", 8),
+    ("vc", 8),
+    ("vd", 8),
+    ("ve", 8),
+    ("vf", 8),
+    ("i", 16),
+    ("pc", 16),
+    ("sp", 8),
+    ("dt", 8),
+    ("st", 8),
+];
+
+/// gdb has no CHIP-8 architecture and assumes the host's without one. The Z80
+/// is the closest it knows: an 8-bit, little endian CPU with 16-bit addresses.
+/// Use gdb-multiarch, as most builds of gdb only know the host.
+const ARCHITECTURE: &str = "z80";
+
+// Breakpoint types of the Z and z packets
+const SOFTWARE_BREAKPOINT: u8 = 0;
+const HARDWARE_BREAKPOINT: u8 = 1;
+const WRITE_WATCHPOINT: u8 = 2;
+
+/// Describes the registers to gdb in its target description format.
+pub fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n",
+    );
+    writeln!(xml, "<architecture>{ARCHITECTURE}</architecture>\n<feature name=\"org.chip8.core\">").unwrap();
+    for (number, (name, bits)) in REGISTERS.iter().enumerate() {
+        let kind = match *name {
+            "pc" => "code_ptr",
+            "i" => "data_ptr",
+            _ => "uint8",
+        };
+        writeln!(xml, "<reg name=\"{name}\" bitsize=\"{bits}\" regnum=\"{number}\" type=\"{kind}\"/>").unwrap();
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+/// The reply gdb expects when the program stops during a frame, if it did.
+pub fn stop_reply(result: &FrameResult) -> Option<String> {
+    if result.exited {
+        return Some(String::from("W00"));
+    }
+    if let Some(e) = result.error {
+        return Some(match e {
+            ExecError::UnknownOpcode { .. } => String::from("S04"),
+            _ => String::from("S0b"),
+        });
+    }
+    result.stop.map(|reason| match reason {
+        StopReason::Paused => String::from("S02"),
+        StopReason::Watchpoint { address, .. } => format!("T05watch:{address:x};"),
+        StopReason::Breakpoint(_) | StopReason::Stepped => String::from("S05"),
+    })
+}
+
+/// The protocol state of one gdb connection.
+#[derive(Default)]
+pub struct Session {
+    no_ack: bool,
+    // True after a continue, until the stop is reported
+    running: bool,
+    // Maps the type and address of every breakpoint set by gdb to its number
+    // in the debugger
+    breakpoints: HashMap<(u8, usize), usize>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// True while gdb waits for the program to stop.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Handles the unescaped contents of a packet and returns the reply. A
+    /// continue is answered once the program stops, so it returns `None`.
+    pub fn handle(&mut self, emulator: &mut Emulator, packet: &[u8]) -> Option<String> {
+        if emulator.get_debugger().is_none() {
+            emulator.set_debugger(Some(Debugger::new()));
+        }
+
+        // Binary data is only valid in X packets, everything else is text
+        if let Some(args) = packet.strip_prefix(b"X") {
+            let written = args.iter().position(|byte| *byte == b':').and_then(|colon| {
+                let (address, length) = parse_range(std::str::from_utf8(&args[..colon]).ok()?)?;
+                let bytes = &args[colon + 1..];
+                if bytes.len() != length {
+                    return None;
+                }
+                emulator.get_processor_mut().write_memory(address, bytes).ok()
+            });
+            return Some(reply_ok(written.is_some()));
+        }
+        let packet = String::from_utf8_lossy(packet);
+        let packet = packet.as_ref();
+
+        let (command, args) = match packet.char_indices().nth(1) {
+            Some((split, _)) => packet.split_at(split),
+            None => (packet, ""),
+        };
+        let reply = match command {
+            "?" => String::from("S05"),
+            "g" => read_registers(emulator.get_processor()),
+            "G" => reply_ok(write_registers(emulator.get_processor_mut(), args)),
+            "p" => match parse_hex(args).and_then(|number| read_register(emulator.get_processor(), number)) {
+                Some(value) => value,
+                None => String::from("E01"),
+            },
+            "P" => {
+                let written = args.split_once('=').and_then(|(number, value)| {
+                    let number = parse_hex(number)?;
+                    let (_, bits) = REGISTERS.get(number)?;
+                    write_register(emulator.get_processor_mut(), number, parse_register(value, *bits)?).then_some(())
+                });
+                reply_ok(written.is_some())
+            }
+            "m" => match parse_range(args) {
+                Some((address, length)) => read_memory(emulator.get_processor(), address, length),
+                None => String::from("E01"),
+            },
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range)?;
+                    let bytes = decode_hex(data)?;
+                    if bytes.len() != length {
+                        return None;
+                    }
+                    emulator.get_processor_mut().write_memory(address, &bytes).ok()
+                });
+                reply_ok(written.is_some())
+            }
+            "Z" | "z" => self.set_breakpoint(emulator, command == "Z", args),
+            "c" => {
+                if let Some(address) = parse_hex(args) {
+                    emulator.get_processor_mut().set_pc(address);
+                }
+                emulator.get_debugger_mut().unwrap().resume();
+                self.running = true;
+                return None;
+            }
+            "s" => {
+                if let Some(address) = parse_hex(args) {
+                    emulator.get_processor_mut().set_pc(address);
+                }
+                emulator.get_debugger_mut().unwrap().pause();
+                let result = emulator.step_instruction();
+                stop_reply(&result).unwrap_or_else(|| String::from("S05"))
+            }
+            "D" | "k" => {
+                self.detach(emulator);
+                String::from("OK")
+            }
+            "H" => String::from("OK"),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Removes the breakpoints set by gdb and lets the program run on.
+    pub fn detach(&mut self, emulator: &mut Emulator) {
+        self.running = false;
+        if let Some(debugger) = emulator.get_debugger_mut() {
+            for (_, number) in self.breakpoints.drain() {
+                debugger.remove(number);
+            }
+            debugger.resume();
+        }
+    }
+
+    fn set_breakpoint(&mut self, emulator: &mut Emulator, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next().and_then(|kind| kind.parse::<u8>().ok());
+        let address = fields.next().and_then(parse_hex);
+        let (kind, address) = match (kind, address) {
+            (Some(kind @ (SOFTWARE_BREAKPOINT | HARDWARE_BREAKPOINT | WRITE_WATCHPOINT)), Some(address)) => {
+                (kind, address)
+            }
+            // Read and access watchpoints aren't supported
+            (Some(_), Some(_)) => return String::new(),
+            _ => return String::from("E01"),
+        };
+
+        let debugger = emulator.get_debugger_mut().unwrap();
+        if insert {
+            self.breakpoints.entry((kind, address)).or_insert_with(|| match kind {
+                WRITE_WATCHPOINT => debugger.add_watchpoint(address),
+                _ => debugger.add_breakpoint(Breakpoint { address: Some(address), condition: None }),
+            });
+        } else if let Some(number) = self.breakpoints.remove(&(kind, address)) {
+            debugger.remove(number);
+        }
+        String::from("OK")
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match parse_range(range) {
+                Some(range) => range,
+                None => return String::from("E01"),
+            };
+            let xml = target_description();
+            let start = offset.min(xml.len());
+            let end = offset.saturating_add(length).min(xml.len());
+            let prefix = if end == xml.len() { 'l' } else { 'm' };
+            return format!("{prefix}{}", &xml[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            "qOffsets" => String::from("Text=0;Data=0;Bss=0"),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Serves one gdb connection at a time on a local TCP port, without
+/// blocking the frame loop.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    // Received bytes that don't form a complete packet yet
+    buffer: Vec<u8>,
+    session: Session,
+}
+
+impl GdbServer {
+    /// Listens for gdb on `port` of the local machine.
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer { listener, client: None })
+    }
+
+    /// Accepts a connection and handles the packets received since the last
+    /// call. The program is paused while gdb connects.
+    pub fn poll(&mut self, emulator: &mut Emulator) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    println!("GDB connected from {address}");
+                    if emulator.get_debugger().is_none() {
+                        emulator.set_debugger(Some(Debugger::new()));
+                    }
+                    emulator.get_debugger_mut().unwrap().pause();
+                    self.client = Some(Client { stream, buffer: Vec::new(), session: Session::new() });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => println!("ERROR Failed to accept GDB connection: {e}"),
+            }
+        }
+
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => return,
+        };
+        match client.poll(emulator) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("GDB disconnected");
+                client.session.detach(emulator);
+                self.client = None;
+            }
+            Err(e) => {
+                println!("ERROR GDB connection failed: {e}");
+                client.session.detach(emulator);
+                self.client = None;
+            }
+        }
+    }
+
+    /// Tells gdb when the program stopped during a frame it continued.
+    pub fn report(&mut self, result: &FrameResult) {
+        if let Some(client) = &mut self.client {
+            if client.session.running {
+                if let Some(reply) = stop_reply(result) {
+                    client.session.running = false;
+                    if let Err(e) = client.send(&reply) {
+                        println!("ERROR Failed to send to GDB: {e}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Handles all complete packets received so far. Returns false once the
+    /// connection is closed.
+    fn poll(&mut self, emulator: &mut Emulator) -> io::Result<bool> {
+        let mut open = true;
+        let mut chunk = [0u8; 4096];
+        self.stream.set_nonblocking(true)?;
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        self.stream.set_nonblocking(false)?;
+
+        while let Some(first) = self.buffer.first() {
+            match *first {
+                b'$' => {
+                    let end = match self.buffer.iter().position(|byte| *byte == b'#') {
+                        Some(end) if self.buffer.len() >= end + 3 => end,
+                        _ => break,
+                    };
+                    // An escaped # is sent as }\x03, so the first # ends the packet
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let valid = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                        == Some(checksum(data));
+                    if !self.session.no_ack {
+                        self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                    }
+                    if !valid {
+                        continue;
+                    }
+
+                    let packet = unescape(data);
+                    if let Some(reply) = self.session.handle(emulator, &packet) {
+                        self.send(&reply)?;
+                    }
+                    if packet.starts_with(b"D") || packet.starts_with(b"k") {
+                        return Ok(false);
+                    }
+                }
+                // Ctrl-C in gdb
+                0x03 => {
+                    self.buffer.remove(0);
+                    emulator.get_debugger_mut().unwrap().pause();
+                    if self.session.running {
+                        self.session.running = false;
+                        self.send("S02")?;
+                    }
+                }
+                // Acknowledgements and noise between packets
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        Ok(open)
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${reply}#{:02x}", checksum(reply.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Undoes the escaping of `}`, `#`, `$` and `*` in packet data, which are
+/// sent as `}` followed by the byte XOR 0x20.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut data = data.iter();
+    while let Some(&byte) = data.next() {
+        match byte {
+            b'}' => bytes.extend(data.next().map(|byte| byte ^ 0x20)),
+            _ => bytes.push(byte),
+        }
+    }
+    bytes
+}
+
+fn reply_ok(ok: bool) -> String {
+    String::from(if ok { "OK" } else { "E01" })
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Parses `address,length`.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn register_value(processor: &Processor, number: usize) -> Option<usize> {
+    match number {
+        0..=15 => Some(processor.get_registers()[number] as usize),
+        16 => Some(processor.get_index()),
+        17 => Some(processor.get_pc()),
+        18 => Some(processor.get_stack().len()),
+        19 => Some(processor.get_delay_timer() as usize),
+        20 => Some(*processor.get_sound_timer() as usize),
+        _ => None,
+    }
+}
+
+fn read_register(processor: &Processor, number: usize) -> Option<String> {
+    let (_, bits) = REGISTERS.get(number)?;
+    let value = register_value(processor, number)?;
+    Some(value.to_le_bytes()[..bits / 8].iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Parses a register value sent by gdb, which is little endian like the ones
+/// it receives.
+fn parse_register(text: &str, bits: usize) -> Option<usize> {
+    let bytes = decode_hex(text)?;
+    if bytes.len() != bits / 8 {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as usize))
+}
+
+fn read_registers(processor: &Processor) -> String {
+    (0..REGISTERS.len()).filter_map(|number| read_register(processor, number)).collect()
+}
+
+/// Writes a register. The stack pointer can't be changed, as the stack
+/// holds nothing but return addresses.
+fn write_register(processor: &mut Processor, number: usize, value: usize) -> bool {
+    match number {
+        0..=15 => processor.set_register(number, value as u8),
+        16 => processor.set_index(value),
+        17 => processor.set_pc(value),
+        18 => return value == processor.get_stack().len(),
+        19 => processor.set_delay_timer(value as u8),
+        20 => processor.set_sound_timer(value as u8),
+        _ => return false,
+    }
+    true
+}
+
+fn write_registers(processor: &mut Processor, data: &str) -> bool {
+    let mut offset = 0;
+    for (number, (_, bits)) in REGISTERS.iter().enumerate() {
+        let value = match data.get(offset..offset + bits / 4).and_then(|text| parse_register(text, *bits)) {
+            Some(value) => value,
+            None => return false,
+        };
+        offset += bits / 4;
+        // Ignore the stack pointer instead of failing the whole packet
+        if number != 18 {
+            write_register(processor, number, value);
+        }
+    }
+    true
+}
+
+fn read_memory(processor: &Processor, address: usize, length: usize) -> String {
+    let ram = processor.get_ram();
+    if address >= ram.len() {
+        return String::from("E01");
+    }
+    let end = address.saturating_add(length).min(ram.len());
+    ram[address..end].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+
+    fn create_emulator() -> Emulator {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v0 := 0x12, i := 0x300, save v0, jump to self
+        processor.load_data(&[0x60, 0x12, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]).unwrap();
+        Emulator::new(processor)
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut emulator = create_emulator();
+        let mut session = Session::new();
+
+        assert_eq!(session.handle(&mut emulator, b"s"), Some(String::from("S05")));
+        let registers = session.handle(&mut emulator, b"g").unwrap();
+        assert_eq!(registers.len(), 16 * 2 + 4 + 4 + 2 + 2 + 2);
+        assert!(registers.starts_with("12"));
+        assert_eq!(session.handle(&mut emulator, b"p11"), Some(String::from("0202")));
+
+        assert_eq!(session.handle(&mut emulator, b"P1=ab"), Some(String::from("OK")));
+        assert_eq!(emulator.get_processor().get_registers()[1], 0xAB);
+        assert_eq!(session.handle(&mut emulator, b"P12=01"), Some(String::from("E01")));
+        // Little endian, like the Z80
+        assert_eq!(session.handle(&mut emulator, b"P10=3412"), Some(String::from("OK")));
+        assert_eq!(emulator.get_processor().get_index(), 0x1234);
+        assert_eq!(session.handle(&mut emulator, b"p10"), Some(String::from("3412")));
+
+        assert_eq!(session.handle(&mut emulator, b"M300,2:beef"), Some(String::from("OK")));
+        assert_eq!(session.handle(&mut emulator, b"m2ff,3"), Some(String::from("00beef")));
+        assert_eq!(session.handle(&mut emulator, b"m10000,1"), Some(String::from("E01")));
+
+        assert_eq!(session.handle(&mut emulator, b"X300,0:"), Some(String::from("OK")));
+        assert_eq!(session.handle(&mut emulator, b"X300,2:#\x00"), Some(String::from("OK")));
+        assert_eq!(session.handle(&mut emulator, b"m300,2"), Some(String::from("2300")));
+        assert_eq!(session.handle(&mut emulator, b"X300,2:#"), Some(String::from("E01")));
+    }
+
+    #[test]
+    fn breakpoints_and_continue() {
+        let mut emulator = create_emulator();
+        let mut session = Session::new();
+
+        assert_eq!(session.handle(&mut emulator, b"Z0,204,2"), Some(String::from("OK")));
+        assert_eq!(session.handle(&mut emulator, b"c"), None);
+        assert!(session.is_running());
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(stop_reply(&result), Some(String::from("S05")));
+        assert_eq!(emulator.get_processor().get_pc(), 0x204);
+
+        assert_eq!(session.handle(&mut emulator, b"z0,204,2"), Some(String::from("OK")));
+        assert_eq!(session.handle(&mut emulator, b"Z2,300,1"), Some(String::from("OK")));
+        session.handle(&mut emulator, b"c");
+        let result = emulator.run_frame(&[false; 16]);
+        assert_eq!(stop_reply(&result), Some(String::from("T05watch:300;")));
+    }
+
+    #[test]
+    fn serves_target_description() {
+        let mut emulator = create_emulator();
+        let mut session = Session::new();
+        let xml = target_description();
+
+        let first = session.handle(&mut emulator, b"qXfer:features:read:target.xml:0,10").unwrap();
+        assert_eq!(first, format!("m{}", &xml[..0x10]));
+        let packet = format!("qXfer:features:read:target.xml:10,{:x}", xml.len());
+        let rest = session.handle(&mut emulator, packet.as_bytes()).unwrap();
+        assert_eq!(rest, format!("l{}", &xml[0x10..]));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" regnum=\"17\" type=\"code_ptr\"/>"));
+    }
+
+    /// Talks to the server over TCP like gdb does, with checksums and
+    /// escaped binary data.
+    #[test]
+    fn serves_over_tcp() {
+        fn send(stream: &mut TcpStream, data: &[u8]) {
+            let mut packet = vec![b'$'];
+            packet.extend_from_slice(data);
+            packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+            stream.write_all(&packet).unwrap();
+        }
+
+        fn receive(server: &mut GdbServer, emulator: &mut Emulator, stream: &mut TcpStream) -> String {
+            let mut received = Vec::new();
+            let mut byte = [0u8];
+            loop {
+                server.poll(emulator);
+                match stream.read(&mut byte) {
+                    Ok(1) => received.push(byte[0]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                    result => panic!("{result:?}"),
+                }
+                // Skip the acknowledgement of our packet
+                let Some(start) = received.iter().position(|byte| *byte == b'$') else { continue };
+                let Some(end) = received.iter().position(|byte| *byte == b'#') else { continue };
+                if received.len() == end + 3 {
+                    let reply = String::from_utf8(received[start + 1..end].to_vec()).unwrap();
+                    let sum = String::from_utf8_lossy(&received[end + 1..]).into_owned();
+                    assert_eq!(sum, format!("{:02x}", checksum(reply.as_bytes())));
+                    return reply;
+                }
+            }
+        }
+
+        let mut emulator = create_emulator();
+        let mut server = GdbServer::bind(0).unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nonblocking(true).unwrap();
+
+        send(&mut stream, b"qSupported:multiprocess+");
+        let reply = receive(&mut server, &mut emulator, &mut stream);
+        assert!(reply.contains("qXfer:features:read+"));
+        assert!(emulator.get_debugger().unwrap().is_paused());
+
+        // #, } and * are escaped
+        send(&mut stream, b"X300,3:}\x03}]}\x0a");
+        assert_eq!(receive(&mut server, &mut emulator, &mut stream), "OK");
+        send(&mut stream, b"m300,3");
+        assert_eq!(receive(&mut server, &mut emulator, &mut stream), "237d2a");
+
+        send(&mut stream, b"g");
+        let registers = receive(&mut server, &mut emulator, &mut stream);
+        assert_eq!(&registers[32..40], "00000002");
+    }
+}
@@ -0,0 +1,383 @@
+use crate::config::ProcessorMode;
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction. Register operands
+/// are register numbers (0x0-0xF), not register values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    LoRes,
+    /// 00FF
+    HiRes,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipIfEqualImmediate(u8, u8),
+    /// 4XNN
+    SkipIfNotEqualImmediate(u8, u8),
+    /// 5XY0
+    SkipIfEqual(u8, u8),
+    /// 5XY2
+    SaveRange(u8, u8),
+    /// 5XY3
+    LoadRange(u8, u8),
+    /// 6XNN
+    SetImmediate(u8, u8),
+    /// 7XNN
+    AddImmediate(u8, u8),
+    /// 8XY0
+    Set(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    Add(u8, u8),
+    /// 8XY5
+    Sub(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7
+    SubReverse(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0
+    SkipIfNotEqual(u8, u8),
+    /// ANNN
+    SetIndex(u16),
+    /// BNNN
+    JumpWithOffset(u16),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// F000 NNNN, the address is stored in the word after the instruction
+    LongIndex,
+    /// FN01
+    SelectPlanes(u8),
+    /// F002
+    LoadAudio,
+    /// FX07
+    GetDelay(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddIndex(u8),
+    /// FX29
+    Font(u8),
+    /// FX30
+    BigFont(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX3A
+    SetPitch(u8),
+    /// FX55
+    Store(u8),
+    /// FX65
+    Load(u8),
+    /// FX75
+    SaveFlags(u8),
+    /// FX85
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    /// Decodes an opcode, returning None if no variant defines it.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nibbles = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            (opcode & 0x000F) as u8,
+        );
+        let nnn = opcode & 0x0FFF;
+        let nn = (opcode & 0x00FF) as u8;
+
+        let instruction = match nibbles {
+            (0x0, 0x0, 0xC, n) => Instruction::ScrollDown(n),
+            (0x0, 0x0, 0xD, n) => Instruction::ScrollUp(n),
+            (0x0, 0x0, 0xE, 0x0) => Instruction::ClearScreen,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+            (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::LoRes,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::HiRes,
+            (0x1, ..) => Instruction::Jump(nnn),
+            (0x2, ..) => Instruction::Call(nnn),
+            (0x3, x, ..) => Instruction::SkipIfEqualImmediate(x, nn),
+            (0x4, x, ..) => Instruction::SkipIfNotEqualImmediate(x, nn),
+            (0x5, x, y, 0x0) => Instruction::SkipIfEqual(x, y),
+            (0x5, x, y, 0x2) => Instruction::SaveRange(x, y),
+            (0x5, x, y, 0x3) => Instruction::LoadRange(x, y),
+            (0x6, x, ..) => Instruction::SetImmediate(x, nn),
+            (0x7, x, ..) => Instruction::AddImmediate(x, nn),
+            (0x8, x, y, 0x0) => Instruction::Set(x, y),
+            (0x8, x, y, 0x1) => Instruction::Or(x, y),
+            (0x8, x, y, 0x2) => Instruction::And(x, y),
+            (0x8, x, y, 0x3) => Instruction::Xor(x, y),
+            (0x8, x, y, 0x4) => Instruction::Add(x, y),
+            (0x8, x, y, 0x5) => Instruction::Sub(x, y),
+            (0x8, x, y, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, x, y, 0x7) => Instruction::SubReverse(x, y),
+            (0x8, x, y, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, x, y, 0x0) => Instruction::SkipIfNotEqual(x, y),
+            (0xA, ..) => Instruction::SetIndex(nnn),
+            (0xB, ..) => Instruction::JumpWithOffset(nnn),
+            (0xC, x, ..) => Instruction::Random(x, nn),
+            (0xD, x, y, n) => Instruction::Draw(x, y, n),
+            (0xE, x, 0x9, 0xE) => Instruction::SkipIfKey(x),
+            (0xE, x, 0xA, 0x1) => Instruction::SkipIfNotKey(x),
+            (0xF, 0x0, 0x0, 0x0) => Instruction::LongIndex,
+            (0xF, n, 0x0, 0x1) => Instruction::SelectPlanes(n),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::LoadAudio,
+            (0xF, x, 0x0, 0x7) => Instruction::GetDelay(x),
+            (0xF, x, 0x0, 0xA) => Instruction::WaitKey(x),
+            (0xF, x, 0x1, 0x5) => Instruction::SetDelay(x),
+            (0xF, x, 0x1, 0x8) => Instruction::SetSound(x),
+            (0xF, x, 0x1, 0xE) => Instruction::AddIndex(x),
+            (0xF, x, 0x2, 0x9) => Instruction::Font(x),
+            (0xF, x, 0x3, 0x0) => Instruction::BigFont(x),
+            (0xF, x, 0x3, 0x3) => Instruction::Bcd(x),
+            (0xF, x, 0x3, 0xA) => Instruction::SetPitch(x),
+            (0xF, x, 0x5, 0x5) => Instruction::Store(x),
+            (0xF, x, 0x6, 0x5) => Instruction::Load(x),
+            (0xF, x, 0x7, 0x5) => Instruction::SaveFlags(x),
+            (0xF, x, 0x8, 0x5) => Instruction::LoadFlags(x),
+            _ => return None,
+        };
+
+        Some(instruction)
+    }
+
+    /// Turns the instruction back into its opcode.
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |base: u16, x: u8, nn: u8| base | (x as u16 & 0xF) << 8 | nn as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16 & 0xF) << 8 | low;
+
+        match *self {
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LoRes => 0x00FE,
+            Instruction::HiRes => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipIfEqualImmediate(x, nn) => xnn(0x3000, x, nn),
+            Instruction::SkipIfNotEqualImmediate(x, nn) => xnn(0x4000, x, nn),
+            Instruction::SkipIfEqual(x, y) => xy(0x5000, x, y),
+            Instruction::SaveRange(x, y) => xy(0x5002, x, y),
+            Instruction::LoadRange(x, y) => xy(0x5003, x, y),
+            Instruction::SetImmediate(x, nn) => xnn(0x6000, x, nn),
+            Instruction::AddImmediate(x, nn) => xnn(0x7000, x, nn),
+            Instruction::Set(x, y) => xy(0x8000, x, y),
+            Instruction::Or(x, y) => xy(0x8001, x, y),
+            Instruction::And(x, y) => xy(0x8002, x, y),
+            Instruction::Xor(x, y) => xy(0x8003, x, y),
+            Instruction::Add(x, y) => xy(0x8004, x, y),
+            Instruction::Sub(x, y) => xy(0x8005, x, y),
+            Instruction::ShiftRight(x, y) => xy(0x8006, x, y),
+            Instruction::SubReverse(x, y) => xy(0x8007, x, y),
+            Instruction::ShiftLeft(x, y) => xy(0x800E, x, y),
+            Instruction::SkipIfNotEqual(x, y) => xy(0x9000, x, y),
+            Instruction::SetIndex(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpWithOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random(x, nn) => xnn(0xC000, x, nn),
+            Instruction::Draw(x, y, n) => xy(0xD000, x, y) | (n as u16 & 0xF),
+            Instruction::SkipIfKey(x) => xnn(0xE000, x, 0x9E),
+            Instruction::SkipIfNotKey(x) => xnn(0xE000, x, 0xA1),
+            Instruction::LongIndex => 0xF000,
+            Instruction::SelectPlanes(n) => fx(n, 0x01),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::GetDelay(x) => fx(x, 0x07),
+            Instruction::WaitKey(x) => fx(x, 0x0A),
+            Instruction::SetDelay(x) => fx(x, 0x15),
+            Instruction::SetSound(x) => fx(x, 0x18),
+            Instruction::AddIndex(x) => fx(x, 0x1E),
+            Instruction::Font(x) => fx(x, 0x29),
+            Instruction::BigFont(x) => fx(x, 0x30),
+            Instruction::Bcd(x) => fx(x, 0x33),
+            Instruction::SetPitch(x) => fx(x, 0x3A),
+            Instruction::Store(x) => fx(x, 0x55),
+            Instruction::Load(x) => fx(x, 0x65),
+            Instruction::SaveFlags(x) => fx(x, 0x75),
+            Instruction::LoadFlags(x) => fx(x, 0x85),
+        }
+    }
+
+    /// The first platform that introduced the instruction.
+    pub fn get_platform(&self) -> ProcessorMode {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LoRes
+            | Instruction::HiRes
+            | Instruction::BigFont(_)
+            | Instruction::SaveFlags(_)
+            | Instruction::LoadFlags(_) => ProcessorMode::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange(..)
+            | Instruction::LoadRange(..)
+            | Instruction::LongIndex
+            | Instruction::SelectPlanes(_)
+            | Instruction::LoadAudio
+            | Instruction::SetPitch(_) => ProcessorMode::XOChip,
+            _ => ProcessorMode::Chip8,
+        }
+    }
+
+    /// Whether a processor running in `mode` executes this instruction.
+    pub fn is_supported(&self, mode: ProcessorMode) -> bool {
+        match self.get_platform() {
+            ProcessorMode::Chip8 => true,
+            ProcessorMode::SuperChip => mode != ProcessorMode::Chip8,
+            ProcessorMode::XOChip => mode == ProcessorMode::XOChip,
+        }
+    }
+
+    /// The number of bytes the instruction occupies, including the address
+    /// word of F000 NNNN.
+    pub fn get_length(&self) -> usize {
+        match self {
+            Instruction::LongIndex => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Formats the instruction in the classic Cowgod mnemonic syntax.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown(n) => write!(f, "SCD {n:#x}"),
+            Instruction::ScrollUp(n) => write!(f, "SCU {n:#x}"),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LoRes => write!(f, "LOW"),
+            Instruction::HiRes => write!(f, "HIGH"),
+            Instruction::Jump(nnn) => write!(f, "JP {nnn:#05x}"),
+            Instruction::Call(nnn) => write!(f, "CALL {nnn:#05x}"),
+            Instruction::SkipIfEqualImmediate(x, nn) => write!(f, "SE V{x:X}, {nn:#04x}"),
+            Instruction::SkipIfNotEqualImmediate(x, nn) => write!(f, "SNE V{x:X}, {nn:#04x}"),
+            Instruction::SkipIfEqual(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{x:X}-V{y:X}"),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{x:X}-V{y:X}"),
+            Instruction::SetImmediate(x, nn) => write!(f, "LD V{x:X}, {nn:#04x}"),
+            Instruction::AddImmediate(x, nn) => write!(f, "ADD V{x:X}, {nn:#04x}"),
+            Instruction::Set(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::Add(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SkipIfNotEqual(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::SetIndex(nnn) => write!(f, "LD I, {nnn:#05x}"),
+            Instruction::JumpWithOffset(nnn) => write!(f, "JP V0, {nnn:#05x}"),
+            Instruction::Random(x, nn) => write!(f, "RND V{x:X}, {nn:#04x}"),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n:#x}"),
+            Instruction::SkipIfKey(x) => write!(f, "SKP V{x:X}"),
+            Instruction::SkipIfNotKey(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::LongIndex => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {n:#x}"),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::GetDelay(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::WaitKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddIndex(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::Font(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::BigFont(x) => write!(f, "LD HF, V{x:X}"),
+            Instruction::Bcd(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::SetPitch(x) => write!(f, "PITCH V{x:X}"),
+            Instruction::Store(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::Load(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{x:X}"),
+            Instruction::LoadFlags(x) => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_operands() {
+        assert_eq!(Instruction::decode(0x8AB4), Some(Instruction::Add(0xA, 0xB)));
+        assert_eq!(Instruction::decode(0xD125), Some(Instruction::Draw(0x1, 0x2, 0x5)));
+        assert_eq!(Instruction::decode(0x3C42), Some(Instruction::SkipIfEqualImmediate(0xC, 0x42)));
+        assert_eq!(Instruction::decode(0xF301), Some(Instruction::SelectPlanes(0x3)));
+    }
+
+    #[test]
+    fn decode_unknown() {
+        assert_eq!(Instruction::decode(0x0123), None);
+        assert_eq!(Instruction::decode(0x5AB1), None);
+        assert_eq!(Instruction::decode(0xE0FF), None);
+        assert_eq!(Instruction::decode(0xF1FF), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Instruction::decode(0x00E0).unwrap().to_string(), "CLS");
+        assert_eq!(Instruction::decode(0x1234).unwrap().to_string(), "JP 0x234");
+        assert_eq!(Instruction::decode(0x6A0F).unwrap().to_string(), "LD VA, 0x0f");
+        assert_eq!(Instruction::decode(0xF265).unwrap().to_string(), "LD V2, [I]");
+    }
+
+    #[test]
+    fn platform_support() {
+        assert!(Instruction::ClearScreen.is_supported(ProcessorMode::Chip8));
+        assert!(!Instruction::HiRes.is_supported(ProcessorMode::Chip8));
+        assert!(Instruction::HiRes.is_supported(ProcessorMode::XOChip));
+        assert!(!Instruction::LongIndex.is_supported(ProcessorMode::SuperChip));
+    }
+}
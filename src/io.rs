@@ -1,48 +1,110 @@
-use minifb::{Key, Menu, Scale, Window, WindowOptions, MENU_KEY_CTRL};
-use crate::config::Palette;
+use minifb::{Key, KeyRepeat, Menu, MenuHandle, Scale, Window, WindowOptions, MENU_KEY_CTRL};
+use crate::audio::{Beeper, Pattern};
+use crate::config::{Palette, ProcessorMode};
+use crate::frontend::{AudioSink, InputSource, VideoSink};
+use crate::processor::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::quirks::{Quirks, QuirksProfile};
 use std::time::Duration;
 
-pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_HEIGHT: usize = 32;
+pub const WINDOW_TITLE: &str = "Chip-8 emulator";
+
+/// The number of save state slots, bound to F1 to F4.
+pub const STATE_SLOTS: usize = 4;
+const SLOT_KEYS: [Key; STATE_SLOTS] = [Key::F1, Key::F2, Key::F3, Key::F4];
 
 pub const MENU_OPEN_FILE_ID: usize = 0;
 pub const MENU_RESET_ID: usize = 1;
 
-const MENU_COLOR_BASE_ID: usize = 5;
+const MENU_QUIRKS_PROFILE_BASE_ID: usize = 10;
+const MENU_COLOR_BASE_ID: usize = 100;
 
 pub const MENU_MODE_CHIP8_ID: usize = 2;
 pub const MENU_MODE_SUPERCHIP_ID: usize = 3;
 pub const MENU_MODE_XOCHIP_ID: usize = 4;
-pub const MENU_COLOR_MATRIX_ID: usize = 5;
-pub const MENU_COLOR_NEON_ID: usize = 6;
-pub const MENU_COLOR_OLDSCHOOL_ID: usize = 7;
+pub const MENU_QUIRK_SHIFT_ID: usize = 20;
+pub const MENU_QUIRK_MEMORY_INCREMENT_ID: usize = 21;
+pub const MENU_QUIRK_VF_RESET_ID: usize = 22;
+pub const MENU_QUIRK_JUMP_WITH_VX_ID: usize = 23;
+pub const MENU_QUIRK_CLIPPING_ID: usize = 24;
+pub const MENU_QUIRK_DISPLAY_WAIT_ID: usize = 25;
+pub const MENU_QUIRK_MEMORY_INCREMENT_BY_X_ID: usize = 26;
+pub const MENU_QUIRK_INDEX_OVERFLOW_ID: usize = 27;
+pub const MENU_RECORD_MOVIE_ID: usize = 5;
+pub const MENU_PLAY_MOVIE_ID: usize = 6;
+pub const MENU_STOP_MOVIE_ID: usize = 7;
+pub const MENU_SAVE_STATE_BASE_ID: usize = 30;
+pub const MENU_LOAD_STATE_BASE_ID: usize = 40;
+pub const MENU_COLOR_MATRIX_ID: usize = 100;
+pub const MENU_COLOR_NEON_ID: usize = 101;
+pub const MENU_COLOR_OLDSCHOOL_ID: usize = 102;
 
 pub enum MenuAction {
     OpenFile,
     Reset,
     SetColors(u32, u32),
+    SetMode(ProcessorMode),
+    SetQuirks(Quirks),
+    /// Saves to a slot from 1 to `STATE_SLOTS`.
+    SaveState(usize),
+    /// Loads from a slot from 1 to `STATE_SLOTS`.
+    LoadState(usize),
+    RecordMovie,
+    PlayMovie,
+    StopMovie,
 }
 
 pub struct IO {
     window: Window,
-    framebuffer: Vec<Vec<bool>>,
-    on_color: u32,
-    off_color: u32,
+    title: String,
+    framebuffer: Vec<Vec<u8>>,
+    colors: [u32; 4],
     current_menu_action: Option<MenuAction>,
-    pallettes: Vec<Palette>
+    pallettes: Vec<Palette>,
+    mode: ProcessorMode,
+    quirks: Quirks,
+    menu_handles: Vec<MenuHandle>,
+    audio: Option<Beeper>,
 }
 
 impl IO {
-    pub fn new(on_color: u32, off_color: u32, pallettes: &Vec<Palette>) -> IO {
+    /// Creates the window. `colors` maps a pixel's plane bitmask to its color:
+    /// background, plane 1, plane 2 and both planes.
+    pub fn new(colors: [u32; 4], pallettes: &[Palette], mode: ProcessorMode, quirks: Quirks) -> IO {
+        let window = Self::create_window(WINDOW_TITLE, DISPLAY_WIDTH, DISPLAY_HEIGHT, colors[0]);
+        let framebuffer = vec![vec![0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+
+        let mut display = IO {
+            window,
+            title: String::from(WINDOW_TITLE),
+            framebuffer,
+            colors,
+            current_menu_action: None,
+            pallettes: pallettes.to_vec(),
+            mode,
+            quirks,
+            menu_handles: Vec::new(),
+            audio: None,
+        };
+
+        display.rebuild_menus();
+        display.refresh_display();
+
+        display
+    }
+
+    /// Opens a window showing a `width` x `height` framebuffer. Hires is
+    /// scaled half as much as lores, so both open at the same size.
+    fn create_window(title: &str, width: usize, height: usize, off_color: u32) -> Window {
+        let scale = if width > DISPLAY_WIDTH { Scale::X8 } else { Scale::X16 };
         let mut window = Window::new(
-            "Chip-8 emulator",
-            DISPLAY_WIDTH,
-            DISPLAY_HEIGHT,
+            title,
+            width,
+            height,
             WindowOptions {
                 borderless: false,
                 title: true,
                 resize: true,
-                scale: Scale::X16,
+                scale,
                 scale_mode: minifb::ScaleMode::AspectRatioStretch,
                 topmost: false,
                 transparency: false,
@@ -51,47 +113,39 @@ impl IO {
         )
         .unwrap();
 
-        let menus = Self::create_menus(pallettes);
-
-        for menu in menus.iter() {
-            window.add_menu(menu);
-        }
-
         window.limit_update_rate(Some(Duration::from_secs_f64(1f64 / 60f64))); //
 
-        let framebuffer = vec![vec![false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
-
         window.set_background_color(
             off_color as usize >> 16,
             (off_color as usize >> 8) & 0xFF,
             off_color as usize & 0xFF,
         );
+        window
+    }
 
-        let mut display = IO {
-            window,
-            framebuffer,
-            on_color,
-            off_color,
-            current_menu_action: None,
-            pallettes: pallettes.clone()
-        };
-
-        display.refresh_display();
+    /// minifb can't change the size of a window's framebuffer, so the window
+    /// is replaced by one of the new size in the same place.
+    fn resize_window(&mut self, width: usize, height: usize) {
+        let position = self.window.get_position();
+        self.window = Self::create_window(&self.title, width, height, self.colors[0]);
+        self.window.set_position(position.0, position.1);
+        self.menu_handles.clear();
+        self.rebuild_menus();
+    }
 
-        display
+    /// Plays the sound timer beep through `beeper`.
+    pub fn set_audio(&mut self, beeper: Beeper) {
+        self.audio = Some(beeper);
     }
 
-    pub fn set_framebuffer(&mut self, other: &Vec<Vec<bool>>) {
-        for x in 0..other.len() {
-            for y in 0..other[x].len() {
-                self.framebuffer[x][y] = other[x][y]
-            }
-        }
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        self.window.set_title(title);
     }
 
-    fn set_colors(&mut self, on_color: u32, off_color: u32) {
-        self.on_color = on_color;
-        self.off_color = off_color;
+    pub fn set_colors(&mut self, on_color: u32, off_color: u32) {
+        self.colors[0] = off_color;
+        self.colors[1] = on_color;
         self.window.set_background_color(
             off_color as usize >> 16,
             (off_color as usize >> 8) & 0xFF,
@@ -99,22 +153,9 @@ impl IO {
         );
     }
 
-    pub fn refresh_display(&mut self) {
-        self.handle_menus();
-
-        let mut buffer_for_screen = Vec::with_capacity(DISPLAY_WIDTH * DISPLAY_HEIGHT);
-        for y in 0..self.framebuffer.len() {
-            for x in 0..self.framebuffer[y].len() {
-                if self.framebuffer[y][x] {
-                    buffer_for_screen.push(self.on_color);
-                } else {
-                    buffer_for_screen.push(self.off_color);
-                }
-            }
-        }
-        self.window
-            .update_with_buffer(&buffer_for_screen, DISPLAY_WIDTH, DISPLAY_HEIGHT)
-            .unwrap();
+    /// True while the rewind key, Backspace, is held.
+    pub fn is_rewind_held(&self) -> bool {
+        self.window.is_key_down(Key::Backspace)
     }
 
     pub fn should_stay_open(&self) -> bool {
@@ -125,33 +166,38 @@ impl IO {
         &self.current_menu_action
     }
 
-    pub fn get_keys(&self) -> [bool; 16] {
-        let mut keys = [false; 16];
+    /// Updates the mode shown as checked in the Mode menu.
+    pub fn set_mode(&mut self, mode: ProcessorMode) {
+        self.mode = mode;
+        self.rebuild_menus();
+    }
 
-        self.window.get_keys().iter().for_each(|key| match key {
-            Key::Key1 => keys[1] = true,
-            Key::Key2 => keys[2] = true,
-            Key::Key3 => keys[3] = true,
-            Key::Key4 => keys[0xC] = true,
-            Key::Q => keys[4] = true,
-            Key::W => keys[5] = true,
-            Key::E => keys[6] = true,
-            Key::R => keys[0xD] = true,
-            Key::A => keys[7] = true,
-            Key::S => keys[8] = true,
-            Key::D => keys[9] = true,
-            Key::F => keys[0xE] = true,
-            Key::Z => keys[0xA] = true,
-            Key::X => keys[0] = true,
-            Key::C => keys[0xB] = true,
-            Key::V => keys[0xF] = true,
-            _ => {}
-        });
+    /// Updates the quirks shown as checked in the Quirks menu.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.rebuild_menus();
+    }
 
-        keys
+    /// Replaces the window menus, so that the active mode and quirks are shown checked.
+    fn rebuild_menus(&mut self) {
+        for handle in self.menu_handles.drain(..) {
+            self.window.remove_menu(handle);
+        }
+
+        for menu in Self::create_menus(&self.pallettes, self.mode, &self.quirks).iter() {
+            self.menu_handles.push(self.window.add_menu(menu));
+        }
+    }
+
+    fn checked_label(name: &str, checked: bool) -> String {
+        if checked {
+            format!("\u{2713} {name}")
+        } else {
+            name.to_string()
+        }
     }
 
-    fn create_menus(pallettes: &Vec<Palette>) -> Vec<Menu> {
+    fn create_menus(pallettes: &[Palette], mode: ProcessorMode, quirks: &Quirks) -> Vec<Menu> {
         let mut file_menu = Menu::new("File").unwrap();
         file_menu
             .add_item("Open", MENU_OPEN_FILE_ID)
@@ -161,18 +207,60 @@ impl IO {
             .add_item("Reset", MENU_RESET_ID)
             .shortcut(Key::R, MENU_KEY_CTRL)
             .build();
+        file_menu.add_separator();
+
+        // The keys are polled by handle_menus, so the items only name them
+        let mut save_menu = Menu::new("Save state").unwrap();
+        let mut load_menu = Menu::new("Load state").unwrap();
+        for i in 0..STATE_SLOTS {
+            save_menu
+                .add_item(&format!("Slot {} (F{})", i + 1, i + 1), MENU_SAVE_STATE_BASE_ID + i)
+                .build();
+            load_menu
+                .add_item(&format!("Slot {} (Shift+F{})", i + 1, i + 1), MENU_LOAD_STATE_BASE_ID + i)
+                .build();
+        }
+        file_menu.add_sub_menu("Save state", &save_menu);
+        file_menu.add_sub_menu("Load state", &load_menu);
+
+        let mut movie_menu = Menu::new("Movie").unwrap();
+        movie_menu.add_item("Record", MENU_RECORD_MOVIE_ID).build();
+        movie_menu.add_item("Play", MENU_PLAY_MOVIE_ID).build();
+        movie_menu.add_item("Stop", MENU_STOP_MOVIE_ID).build();
+        file_menu.add_sub_menu("Movie", &movie_menu);
 
         let mut options_menu = Menu::new("Options").unwrap();
 
         let mut mode_menu = Menu::new("Mode").unwrap();
-        mode_menu
-            .add_item("Chip-8", MENU_MODE_CHIP8_ID)
-            .enabled(false)
-            .build();
-        mode_menu
-            .add_item("SUPER-CHIP", MENU_MODE_SUPERCHIP_ID)
-            .build();
-        mode_menu.add_item("XO-CHIP", MENU_MODE_XOCHIP_ID).build();
+        for (name, id, item_mode) in [
+            ("Chip-8", MENU_MODE_CHIP8_ID, ProcessorMode::Chip8),
+            ("SUPER-CHIP", MENU_MODE_SUPERCHIP_ID, ProcessorMode::SuperChip),
+            ("XO-CHIP", MENU_MODE_XOCHIP_ID, ProcessorMode::XOChip),
+        ] {
+            let active = item_mode == mode;
+            mode_menu.add_item(&Self::checked_label(name, active), id).enabled(!active).build();
+        }
+
+        let mut quirks_menu = Menu::new("Quirks").unwrap();
+        for (i, profile) in QuirksProfile::ALL.iter().enumerate() {
+            let active = profile.quirks() == *quirks;
+            quirks_menu
+                .add_item(&Self::checked_label(profile.get_title(), active), MENU_QUIRKS_PROFILE_BASE_ID + i)
+                .build();
+        }
+        quirks_menu.add_separator();
+        for (name, id, enabled) in [
+            ("Shift VX in place", MENU_QUIRK_SHIFT_ID, quirks.shift),
+            ("Increment I on load/store", MENU_QUIRK_MEMORY_INCREMENT_ID, quirks.memory_increment),
+            ("Reset VF on logic ops", MENU_QUIRK_VF_RESET_ID, quirks.vf_reset),
+            ("Jump with VX", MENU_QUIRK_JUMP_WITH_VX_ID, quirks.jump_with_vx),
+            ("Clip sprites", MENU_QUIRK_CLIPPING_ID, quirks.clipping),
+            ("Wait for display", MENU_QUIRK_DISPLAY_WAIT_ID, quirks.display_wait),
+            ("Increment I by X otherwise", MENU_QUIRK_MEMORY_INCREMENT_BY_X_ID, quirks.memory_increment_by_x),
+            ("Set VF on I overflow", MENU_QUIRK_INDEX_OVERFLOW_ID, quirks.index_overflow),
+        ] {
+            quirks_menu.add_item(&Self::checked_label(name, enabled), id).build();
+        }
 
         let mut color_menu = Menu::new("Colors").unwrap();
 
@@ -181,6 +269,7 @@ impl IO {
         }
 
         options_menu.add_sub_menu("Mode", &mode_menu);
+        options_menu.add_sub_menu("Quirks", &quirks_menu);
         options_menu.add_sub_menu("Colors", &color_menu);
 
         vec![file_menu, options_menu]
@@ -195,6 +284,62 @@ impl IO {
                 MENU_RESET_ID => {
                     self.current_menu_action = Some(MenuAction::Reset);
                 }
+                MENU_RECORD_MOVIE_ID => {
+                    self.current_menu_action = Some(MenuAction::RecordMovie);
+                }
+                MENU_PLAY_MOVIE_ID => {
+                    self.current_menu_action = Some(MenuAction::PlayMovie);
+                }
+                MENU_STOP_MOVIE_ID => {
+                    self.current_menu_action = Some(MenuAction::StopMovie);
+                }
+                MENU_MODE_CHIP8_ID | MENU_MODE_SUPERCHIP_ID | MENU_MODE_XOCHIP_ID => {
+                    let mode = match menu_id {
+                        MENU_MODE_CHIP8_ID => ProcessorMode::Chip8,
+                        MENU_MODE_SUPERCHIP_ID => ProcessorMode::SuperChip,
+                        _ => ProcessorMode::XOChip,
+                    };
+                    self.mode = mode;
+                    self.rebuild_menus();
+                    self.current_menu_action = Some(MenuAction::SetMode(mode));
+                }
+                MENU_QUIRKS_PROFILE_BASE_ID..MENU_QUIRK_SHIFT_ID => {
+                    let index = menu_id - MENU_QUIRKS_PROFILE_BASE_ID;
+                    match QuirksProfile::ALL.get(index) {
+                        Some(profile) => {
+                            self.set_quirks(profile.quirks());
+                            self.current_menu_action = Some(MenuAction::SetQuirks(self.quirks));
+                        }
+                        None => self.current_menu_action = None,
+                    }
+                }
+                MENU_SAVE_STATE_BASE_ID..MENU_LOAD_STATE_BASE_ID if menu_id - MENU_SAVE_STATE_BASE_ID < STATE_SLOTS => {
+                    self.current_menu_action = Some(MenuAction::SaveState(menu_id - MENU_SAVE_STATE_BASE_ID + 1));
+                }
+                MENU_LOAD_STATE_BASE_ID..MENU_COLOR_BASE_ID if menu_id - MENU_LOAD_STATE_BASE_ID < STATE_SLOTS => {
+                    self.current_menu_action = Some(MenuAction::LoadState(menu_id - MENU_LOAD_STATE_BASE_ID + 1));
+                }
+                MENU_QUIRK_SHIFT_ID..MENU_COLOR_BASE_ID => {
+                    let mut quirks = self.quirks;
+                    match menu_id {
+                        MENU_QUIRK_SHIFT_ID => quirks.shift = !quirks.shift,
+                        MENU_QUIRK_MEMORY_INCREMENT_ID => quirks.memory_increment = !quirks.memory_increment,
+                        MENU_QUIRK_VF_RESET_ID => quirks.vf_reset = !quirks.vf_reset,
+                        MENU_QUIRK_JUMP_WITH_VX_ID => quirks.jump_with_vx = !quirks.jump_with_vx,
+                        MENU_QUIRK_CLIPPING_ID => quirks.clipping = !quirks.clipping,
+                        MENU_QUIRK_DISPLAY_WAIT_ID => quirks.display_wait = !quirks.display_wait,
+                        MENU_QUIRK_MEMORY_INCREMENT_BY_X_ID => {
+                            quirks.memory_increment_by_x = !quirks.memory_increment_by_x
+                        }
+                        MENU_QUIRK_INDEX_OVERFLOW_ID => quirks.index_overflow = !quirks.index_overflow,
+                        _ => {
+                            self.current_menu_action = None;
+                            return;
+                        }
+                    }
+                    self.set_quirks(quirks);
+                    self.current_menu_action = Some(MenuAction::SetQuirks(quirks));
+                }
                 MENU_COLOR_BASE_ID.. => {
                     let index = menu_id - MENU_COLOR_BASE_ID;
                     if index >= self.pallettes.len() {
@@ -208,7 +353,82 @@ impl IO {
                 _ => {}
             }
         } else {
-            self.current_menu_action = None;
+            self.current_menu_action = self.slot_key_action();
+        }
+    }
+
+    /// F1 to F4 save to a slot, and load from it with Shift held. Menu
+    /// shortcuts aren't reported by minifb on X11, so the keys are polled.
+    fn slot_key_action(&self) -> Option<MenuAction> {
+        let slot = SLOT_KEYS.iter().position(|key| self.window.is_key_pressed(*key, KeyRepeat::No))? + 1;
+        if self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift) {
+            Some(MenuAction::LoadState(slot))
+        } else {
+            Some(MenuAction::SaveState(slot))
+        }
+    }
+}
+
+impl VideoSink for IO {
+    /// Copies the processor framebuffer, resizing the window when the
+    /// program switches between lores and hires mode.
+    fn set_framebuffer(&mut self, other: &[Vec<u8>]) {
+        if other.len() != self.framebuffer.len() || other[0].len() != self.framebuffer[0].len() {
+            self.resize_window(other[0].len(), other.len());
+        }
+        self.framebuffer = other.to_vec();
+    }
+
+    fn refresh_display(&mut self) {
+        self.handle_menus();
+
+        let height = self.framebuffer.len();
+        let width = self.framebuffer[0].len();
+
+        let mut buffer_for_screen = Vec::with_capacity(width * height);
+        for row in &self.framebuffer {
+            for pixel in row {
+                buffer_for_screen.push(self.colors[(*pixel & 0x3) as usize]);
+            }
+        }
+        self.window
+            .update_with_buffer(&buffer_for_screen, width, height)
+            .unwrap();
+    }
+}
+
+impl InputSource for IO {
+    fn get_keys(&mut self) -> [bool; 16] {
+        let mut keys = [false; 16];
+
+        self.window.get_keys().iter().for_each(|key| match key {
+            Key::Key1 => keys[1] = true,
+            Key::Key2 => keys[2] = true,
+            Key::Key3 => keys[3] = true,
+            Key::Key4 => keys[0xC] = true,
+            Key::Q => keys[4] = true,
+            Key::W => keys[5] = true,
+            Key::E => keys[6] = true,
+            Key::R => keys[0xD] = true,
+            Key::A => keys[7] = true,
+            Key::S => keys[8] = true,
+            Key::D => keys[9] = true,
+            Key::F => keys[0xE] = true,
+            Key::Z => keys[0xA] = true,
+            Key::X => keys[0] = true,
+            Key::C => keys[0xB] = true,
+            Key::V => keys[0xF] = true,
+            _ => {}
+        });
+
+        keys
+    }
+}
+
+impl AudioSink for IO {
+    fn play_frame(&mut self, beeping: bool, pattern: Option<&Pattern>) {
+        if let Some(audio) = &mut self.audio {
+            audio.play_frame(beeping, pattern);
         }
     }
 }
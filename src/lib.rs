@@ -1,6 +1,25 @@
+pub mod assembler;
+pub mod audio;
 pub mod config;
+pub mod console;
+pub mod debugger;
+pub mod disassembler;
+pub mod dump;
+pub mod emulator;
 pub mod font;
+pub mod gdb;
+pub mod frontend;
+pub mod instruction;
+#[cfg(feature = "window")]
 pub mod io;
+pub mod movie;
 pub mod processor;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
 pub mod rom;
-pub mod splash;
\ No newline at end of file
+pub mod romdb;
+pub mod splash;
+pub mod state;
+pub mod trace;
+pub mod tracediff;
\ No newline at end of file
@@ -1,71 +1,450 @@
 #![windows_subsystem = "windows"]
 
-use chip8_emulator::{io::{self, MenuAction::OpenFile, MenuAction::Reset, MenuAction::SetColors}, rom, processor::{self, Processor}, splash, config};
+use chip8_emulator::{io::{self, MenuAction::OpenFile, MenuAction::Reset, MenuAction::SetColors, MenuAction::SetMode, MenuAction::SetQuirks, MenuAction::SaveState, MenuAction::LoadState, MenuAction::RecordMovie, MenuAction::PlayMovie, MenuAction::StopMovie}, rom, processor::Processor, quirks::QuirksProfile, splash, config, emulator::Emulator};
+use chip8_emulator::audio::{Beeper, DeviceOutput, WavWriter};
+use chip8_emulator::console::{self, Console};
+use chip8_emulator::debugger::Debugger;
+use chip8_emulator::emulator::FrameResult;
+use chip8_emulator::gdb::GdbServer;
+use chip8_emulator::frontend::InputSource;
+use chip8_emulator::movie::Movie;
+use chip8_emulator::random::{self, RandomMode};
+use chip8_emulator::rewind::Rewind;
+use chip8_emulator::romdb::{self, RomDatabase, RomInfo};
+use chip8_emulator::trace::Tracer;
 use native_dialog::FileDialog;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-const INSTRUCTIONS_PER_FRAME: u32 = 15;
+// Save states are kept in this directory next to config.ini
+const STATES_DIRECTORY: &str = "states";
+
+enum MovieMode {
+    Off,
+    Recording(Movie),
+    /// Plays the movie, the number is the next frame.
+    Playing(Movie, usize),
+}
 
 fn main() {
     let mut config = config::Config::build().unwrap();
+    let rom_database = RomDatabase::build();
+
+    let mut rom = splash::SPLASH.to_vec();
+    let mut rom_name = String::from("splash");
+    let mut rom_info = rom_database.lookup(&rom::hash(&rom)).cloned();
+    let settings = rom_config(&config, rom_info.as_ref());
+
+    // Read once, a missing interpreter is shown in the window title for the whole session
+    let vip_page = load_vip_page(&config);
+    let seeded_fallback = config.random == RandomMode::CosmacVip && vip_page.is_none();
+
+    let mut processor = create_processor(&settings, vip_page.as_ref());
+    // The splash screen fits in every mode
+    processor.load_data(&rom).unwrap();
+
+    let mut emulator = Emulator::new(processor);
+    emulator.set_instructions_per_frame(settings.instructions_per_frame);
+    emulator.set_rewind(create_rewind(&config));
 
-    let mut processor = processor::Processor::new();
+    if let Some(trace_output) = &config.trace_output {
+        match Tracer::create(Path::new(trace_output), config.trace_format, config.trace_filter.clone()) {
+            Ok(tracer) => emulator.set_tracer(Some(tracer)),
+            Err(e) => println!("ERROR Failed to create {trace_output}: {e}"),
+        }
+    }
 
-    processor.load_data(&splash::SPLASH);
+    let mut console = None;
+    if config.debug_console {
+        emulator.set_debugger(Some(Debugger::new()));
+        console = Some(Console::spawn());
+        println!("Debug console ready, type help for a list of commands");
+    }
+    let mut gdb = None;
+    if let Some(port) = config.gdb_port {
+        match GdbServer::bind(port) {
+            Ok(server) => {
+                println!("Waiting for GDB on port {port}");
+                gdb = Some(server);
+            }
+            Err(e) => println!("ERROR Failed to listen for GDB on port {port}: {e}"),
+        }
+    }
 
-    let mut io = io::IO::new(config.on_color, config.off_color,  config.get_palettes());
+    let mut movie_mode = MovieMode::Off;
+
+    let mut io = io::IO::new(settings.get_colors(), config.get_palettes(), settings.mode, settings.quirks);
+    let mut title = window_title(rom_info.as_ref(), seeded_fallback);
+    io.set_title(&title);
+    let mut beeper = match DeviceOutput::open() {
+        Ok(device) => Some(Beeper::new(config.tone, Box::new(device))),
+        Err(e) => {
+            println!("ERROR Failed to open the sound device: {e}");
+            None
+        }
+    };
+    if let Some(wav_output) = &config.wav_output {
+        match WavWriter::create(Path::new(wav_output)) {
+            Ok(wav) => match &mut beeper {
+                Some(beeper) => beeper.add_backend(Box::new(wav)),
+                None => beeper = Some(Beeper::new(config.tone, Box::new(wav))),
+            },
+            Err(e) => println!("ERROR Failed to create {wav_output}: {e}"),
+        }
+    }
+    if let Some(beeper) = beeper {
+        io.set_audio(beeper);
+    }
 
     while io.should_stay_open() {
         if let Some(action) = io.get_current_menu_action() {
             match action {
-                OpenFile => load_rom(&mut processor),
-                Reset => processor.reset(),
+                OpenFile => {
+                    if let Some((name, data)) = load_rom() {
+                        let hash = rom::hash(&data);
+                        let info = rom_database.lookup(&hash).cloned();
+
+                        // Settings from the database only last until the next rom
+                        let settings = rom_config(&config, info.as_ref());
+                        let mut processor = create_processor(&settings, vip_page.as_ref());
+                        match processor.load_data(&data) {
+                            Ok(()) => {
+                                stop_movie(&mut movie_mode);
+                                print_rom_info(&hash, info.as_ref());
+                                rom_info = info;
+                                emulator.set_processor(processor);
+                                emulator.set_instructions_per_frame(settings.instructions_per_frame);
+                                io.set_mode(settings.mode);
+                                io.set_quirks(settings.quirks);
+                                io.set_colors(settings.on_color, settings.off_color);
+                                rom = data;
+                                rom_name = name;
+                                title = window_title(rom_info.as_ref(), seeded_fallback);
+                                io.set_title(&title);
+                            }
+                            Err(e) => println!("ERROR Failed to load {name}: {e}"),
+                        }
+                    }
+                }
+                Reset => {
+                    stop_movie(&mut movie_mode);
+                    emulator.reset();
+                    io.set_title(&title);
+                }
                 SetColors(on_color, off_color) => {
                     config.on_color = *on_color;
                     config.off_color = *off_color;
-                    match config.save_to_file() {
-                        Ok(()) => println!("Saved configuration succesfully"),
-                        Err(e) => println!("ERROR Failed to save configuration file: {e}"),
+                    save_config(&config);
+                }
+                SetMode(mode) => {
+                    let mut mode_config = config.clone();
+                    mode_config.mode = *mode;
+                    mode_config.quirks = QuirksProfile::for_mode(*mode).quirks();
+                    let mut processor = create_processor(&mode_config, vip_page.as_ref());
+                    match processor.load_data(&rom) {
+                        Ok(()) => {
+                            stop_movie(&mut movie_mode);
+                            // Choosing a mode overrides the one from the database
+                            if let Some(info) = &mut rom_info {
+                                info.mode = None;
+                                info.quirks = None;
+                            }
+                            config = mode_config;
+                            emulator.set_processor(processor);
+                            io.set_quirks(config.quirks);
+                            io.set_title(&title);
+                            save_config(&config);
+                        }
+                        Err(e) => {
+                            println!("ERROR Can't switch to {}: {e}", mode.get_name());
+                            io.set_mode(emulator.get_processor().get_mode());
+                        }
                     }
                 }
+                SetQuirks(quirks) => {
+                    stop_movie(&mut movie_mode);
+                    emulator.get_processor_mut().set_quirks(*quirks);
+                    // Quirks from the rom database are changed for this rom only, config.ini keeps the defaults
+                    match rom_info.as_mut().filter(|info| info.quirks.is_some()) {
+                        Some(info) => info.quirks = Some(*quirks),
+                        None => {
+                            config.quirks = *quirks;
+                            save_config(&config);
+                        }
+                    }
+                }
+                SaveState(slot) => {
+                    let path = state_path(&rom_name, *slot);
+                    let result = fs::create_dir_all(STATES_DIRECTORY)
+                        .and_then(|_| fs::write(&path, emulator.get_processor().save_state()));
+                    match result {
+                        Ok(()) => println!("Saved state to {}", path.display()),
+                        Err(e) => println!("ERROR Failed to save state to {}: {e}", path.display()),
+                    }
+                }
+                LoadState(slot) => {
+                    stop_movie(&mut movie_mode);
+                    let path = state_path(&rom_name, *slot);
+                    match fs::read(&path) {
+                        Ok(data) => match emulator.load_state(&data) {
+                            Ok(()) => {
+                                println!("Loaded state from {}", path.display());
+                                io.set_title(&title);
+                            }
+                            Err(e) => println!("ERROR Failed to load state from {}: {e}", path.display()),
+                        },
+                        Err(e) => println!("ERROR Failed to read {}: {e}", path.display()),
+                    }
+                }
+                RecordMovie => {
+                    stop_movie(&mut movie_mode);
+                    // Record from power on, so the movie can be replayed exactly
+                    let mut processor = create_processor(&rom_config(&config, rom_info.as_ref()), vip_page.as_ref());
+                    match processor.load_data(&rom) {
+                        Ok(()) => {
+                            emulator.set_processor(processor);
+                            movie_mode = MovieMode::Recording(Movie::new(&emulator));
+                            io.set_title(&format!("{title} - recording"));
+                        }
+                        Err(e) => println!("ERROR Failed to record: {e}"),
+                    }
+                }
+                PlayMovie => {
+                    stop_movie(&mut movie_mode);
+                    if let Some(movie) = load_movie() {
+                        match movie.create_emulator(&rom) {
+                            Ok(mut movie_emulator) => {
+                                // Keep the breakpoints and the trace
+                                movie_emulator.set_debugger(emulator.get_debugger_mut().map(std::mem::take));
+                                movie_emulator.set_tracer(emulator.take_tracer());
+                                emulator = movie_emulator;
+                                movie_mode = MovieMode::Playing(movie, 0);
+                                io.set_title(&format!("{title} - playing"));
+                            }
+                            Err(e) => println!("ERROR Failed to play movie: {e}"),
+                        }
+                    }
+                }
+                StopMovie => {
+                    stop_movie(&mut movie_mode);
+                    io.set_title(&title);
+                }
+            }
+        }
+
+        if io.is_rewind_held() && matches!(movie_mode, MovieMode::Off) {
+            let was_halted = emulator.is_halted();
+            emulator.rewind(&mut io);
+            if was_halted && !emulator.is_halted() {
+                io.set_title(&title);
+            }
+            continue;
+        }
+
+        let was_paused = emulator.is_paused();
+        if let Some(console) = &mut console {
+            console.poll(&mut emulator);
+        }
+        if let Some(gdb) = &mut gdb {
+            gdb.poll(&mut emulator);
+        }
+        if was_paused && !emulator.is_paused() {
+            io.set_title(&title);
+        } else if !was_paused && emulator.is_paused() {
+            io.set_title(&format!("{title} - paused"));
+        }
+
+        // Time stands still while paused, so a recording doesn't get the keys
+        if emulator.is_paused() {
+            emulator.present(&mut io, &FrameResult::default());
+            continue;
+        }
+
+        let keys = match &mut movie_mode {
+            MovieMode::Off => io.get_keys(),
+            MovieMode::Recording(movie) => {
+                let keys = io.get_keys();
+                movie.record(&keys);
+                keys
             }
+            MovieMode::Playing(movie, frame) => match movie.get_keys(*frame) {
+                Some(keys) => {
+                    *frame += 1;
+                    keys
+                }
+                None => {
+                    // Continue with the live keys from where the movie ended
+                    println!("Movie finished");
+                    movie_mode = MovieMode::Off;
+                    emulator.set_rewind(create_rewind(&config));
+                    io.set_title(&title);
+                    io.get_keys()
+                }
+            },
+        };
+
+        let result = emulator.run_frame(&keys);
+        emulator.present(&mut io, &result);
+        if let Some(gdb) = &mut gdb {
+            gdb.report(&result);
         }
+        if let Some(reason) = result.stop {
+            print!("Stopped: {reason}\n{}", console::location(emulator.get_processor()));
+            io.set_title(&format!("{title} - paused"));
+        }
+        if let Some(e) = result.error {
+            println!("ERROR Program halted: {e}");
+            io.set_title(&format!("{title} - halted: {e}"));
+        }
+    }
+}
+
+fn create_processor(config: &config::Config, vip_page: Option<&[u8; 256]>) -> Processor {
+    let mut processor = Processor::new(config.mode);
+    processor.set_quirks(config.quirks);
+    processor.set_strict(config.strict);
+    if let Some(stack_limit) = config.stack_limit {
+        processor.set_stack_limit(stack_limit);
+    }
+    if let Some(seed) = config.seed {
+        processor.set_seed(seed);
+    }
+    processor.set_vip_random(vip_page.copied());
+    processor
+}
+
+/// The page of the COSMAC VIP interpreter used by the cosmac-vip random mode,
+/// if that is selected and the interpreter can be read.
+fn load_vip_page(config: &config::Config) -> Option<[u8; 256]> {
+    if config.random != RandomMode::CosmacVip {
+        return None;
+    }
+    let result = match &config.vip_interpreter {
+        Some(path) => {
+            random::load_vip_page(Path::new(path)).map_err(|e| format!("Failed to load VIP interpreter {path}: {e}"))
+        }
+        None => Err(String::from("The cosmac-vip random mode needs vip_interpreter to be set")),
+    };
+    match result {
+        Ok(page) => Some(page),
+        Err(e) => {
+            println!("ERROR {e}, using the seeded random number generator instead");
+            None
+        }
+    }
+}
+
+/// The configuration with the settings the database has for the rom.
+fn rom_config(config: &config::Config, rom_info: Option<&RomInfo>) -> config::Config {
+    let mut settings = config.clone();
+    if let Some(info) = rom_info {
+        info.apply(&mut settings);
+    }
+    settings
+}
+
+fn window_title(rom_info: Option<&RomInfo>, seeded_fallback: bool) -> String {
+    let mut title = match rom_info.and_then(|info| info.title.as_deref()) {
+        Some(title) => format!("{} - {title}", io::WINDOW_TITLE),
+        None => String::from(io::WINDOW_TITLE),
+    };
+    if seeded_fallback {
+        title.push_str(" - seeded random, VIP interpreter missing");
+    }
+    title
+}
 
-        let mut instructions_this_frame = 0;
-        let mut vram_changed = false;
+/// Tells what the database knows about the rom, or the hash to add it with.
+fn print_rom_info(hash: &[u8; 32], rom_info: Option<&RomInfo>) {
+    let Some(info) = rom_info else {
+        println!("Not in the rom database, add it to {} as [{}]", romdb::USER_DATABASE, rom::hash_to_hex(hash));
+        return;
+    };
+    match (&info.title, &info.author) {
+        (Some(title), Some(author)) => println!("{title} by {author}"),
+        (Some(title), None) => println!("{title}"),
+        _ => {}
+    }
+    if let Some(key_hints) = &info.key_hints {
+        println!("Keys: {key_hints}");
+    }
+}
 
-        processor.set_keys(&io.get_keys());
-        processor.update_timers();
+fn create_rewind(config: &config::Config) -> Option<Rewind> {
+    if config.rewind_seconds == 0 {
+        return None;
+    }
+    let frames = config.rewind_seconds as usize * 60;
+    let bytes = config.rewind_memory_mb as usize * 1024 * 1024;
+    Some(Rewind::new(frames, bytes))
+}
 
-        while instructions_this_frame < INSTRUCTIONS_PER_FRAME && !vram_changed {
-            vram_changed = vram_changed | processor.step();
-            instructions_this_frame += 1;
+/// Ends playback, or asks where to save the recording.
+fn stop_movie(movie_mode: &mut MovieMode) {
+    if let MovieMode::Recording(movie) = std::mem::replace(movie_mode, MovieMode::Off) {
+        let path = FileDialog::new()
+            .add_filter("Chip-8 Movie", &["c8m"])
+            .show_save_single_file()
+            .unwrap();
+        if let Some(path) = path {
+            match fs::write(&path, movie.to_bytes()) {
+                Ok(()) => println!("Saved movie to {}", path.display()),
+                Err(e) => println!("ERROR Failed to save movie to {}: {e}", path.display()),
+            }
         }
+    }
+}
 
-        if vram_changed {
-            io.set_framebuffer(processor.get_framebuffer());
+fn load_movie() -> Option<Movie> {
+    let path = FileDialog::new()
+        .add_filter("Chip-8 Movie", &["c8m"])
+        .show_open_single_file()
+        .unwrap()?;
+
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("ERROR Failed to read {}: {e}", path.display());
+            return None;
+        }
+    };
+    match Movie::from_bytes(&data) {
+        Ok(movie) => Some(movie),
+        Err(e) => {
+            println!("ERROR Failed to load {}: {e}", path.display());
+            None
         }
-        io.refresh_display();
     }
 }
 
-fn load_rom(processor: &mut Processor) {
+fn save_config(config: &config::Config) {
+    match config.save_to_file() {
+        Ok(()) => println!("Saved configuration succesfully"),
+        Err(e) => println!("ERROR Failed to save configuration file: {e}"),
+    }
+}
+
+fn state_path(rom_name: &str, slot: usize) -> PathBuf {
+    Path::new(STATES_DIRECTORY).join(format!("{rom_name}.slot{slot}.state"))
+}
+
+/// Asks for a rom and returns its file name without extension and its contents.
+fn load_rom() -> Option<(String, Vec<u8>)> {
 
     let path = FileDialog::new()
     .add_filter("Chip-8 Rom", &["ch8"])
     .show_open_single_file()
     .unwrap();
-    
-    let rom_path = match path {
-        Some(path) => path,
-        None => return,
-    };
+
+    let rom_path = path?;
 
     let rom = rom::load(&rom_path).unwrap();
 
     println!("{}", rom_path.file_name().unwrap().to_str().unwrap());
 
-    processor.load_data(&rom);
-    processor.reset();
+    let name = rom_path.file_stem().unwrap().to_string_lossy().into_owned();
+
+    Some((name, rom))
 }
 
 //4A10
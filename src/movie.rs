@@ -0,0 +1,231 @@
+use crate::config::ProcessorMode;
+use crate::emulator::Emulator;
+use crate::processor::Processor;
+use crate::quirks::Quirks;
+use crate::state::{self, StateError, StateReader, StateWriter};
+use std::error::Error;
+use std::fmt;
+
+/// The first bytes of every movie file.
+pub const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+/// Incremented whenever the layout of a movie changes.
+pub const MOVIE_VERSION: u8 = 4;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MovieError {
+    /// The data is not a movie.
+    BadMagic,
+    /// The movie was written by a different version of the emulator.
+    UnsupportedVersion(u8),
+    /// The movie was recorded with another rom.
+    RomMismatch,
+    /// The data ends early or holds an impossible value.
+    Corrupt,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(f, "unsupported movie version {version}"),
+            MovieError::RomMismatch => write!(f, "movie was recorded with another rom"),
+            MovieError::Corrupt => write!(f, "movie is corrupt"),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(_: StateError) -> Self {
+        MovieError::Corrupt
+    }
+}
+
+/// A recording of the keys pressed in every frame, together with everything
+/// needed to replay it exactly: the rom, mode, quirks, speed, stack limit,
+/// strict mode and the seed of the random number generator.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+    pub mode: ProcessorMode,
+    pub rom_hash: [u8; 32],
+    pub quirks: Quirks,
+    pub seed: u64,
+    /// The interpreter page used by the COSMAC VIP random routine, if selected.
+    pub vip_random: Option<[u8; 256]>,
+    pub instructions_per_frame: u32,
+    pub stack_limit: usize,
+    pub strict: bool,
+    /// One bitmask per frame, bit N set while key N is held.
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Starts an empty recording of the emulator, which should have just
+    /// loaded its rom.
+    pub fn new(emulator: &Emulator) -> Movie {
+        let processor = emulator.get_processor();
+        Movie {
+            mode: processor.get_mode(),
+            rom_hash: *processor.get_rom_hash(),
+            quirks: *processor.get_quirks(),
+            seed: processor.get_seed(),
+            vip_random: processor.get_vip_random().copied(),
+            instructions_per_frame: emulator.get_instructions_per_frame(),
+            stack_limit: processor.get_stack_limit(),
+            strict: processor.is_strict(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Creates an emulator set up the way it was when recording started.
+    pub fn create_emulator(&self, rom: &[u8]) -> Result<Emulator, MovieError> {
+        let mut processor = Processor::new(self.mode);
+        // A rom too large for the mode can't be the one recorded
+        if processor.load_data(rom).is_err() || *processor.get_rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        processor.set_quirks(self.quirks);
+        processor.set_seed(self.seed);
+        processor.set_vip_random(self.vip_random);
+        processor.set_stack_limit(self.stack_limit);
+        processor.set_strict(self.strict);
+
+        let mut emulator = Emulator::new(processor);
+        emulator.set_instructions_per_frame(self.instructions_per_frame);
+        Ok(emulator)
+    }
+
+    pub fn record(&mut self, keys: &[bool; 16]) {
+        self.frames.push(state::keys_to_bits(keys));
+    }
+
+    /// The keys held in `frame`, or `None` after the end of the movie.
+    pub fn get_keys(&self, frame: usize) -> Option<[bool; 16]> {
+        self.frames.get(frame).map(|bits| state::keys_from_bits(*bits))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(MOVIE_MAGIC);
+        writer.write_u8(MOVIE_VERSION);
+        writer.write_u8(state::mode_to_byte(self.mode));
+        writer.write_bytes(&self.rom_hash);
+        writer.write_u8(self.quirks.to_bits());
+        writer.write_u64(self.seed);
+        writer.write_bool(self.vip_random.is_some());
+        if let Some(page) = &self.vip_random {
+            writer.write_bytes(page);
+        }
+        writer.write_u32(self.instructions_per_frame);
+        writer.write_u32(self.stack_limit as u32);
+        writer.write_bool(self.strict);
+        writer.write_u32(self.frames.len() as u32);
+        for bits in &self.frames {
+            writer.write_u16(*bits);
+        }
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(MOVIE_MAGIC.len()).ok() != Some(MOVIE_MAGIC.as_slice()) {
+            return Err(MovieError::BadMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let mode = state::mode_from_byte(reader.read_u8()?).ok_or(MovieError::Corrupt)?;
+        let rom_hash = reader.read_bytes(32)?.try_into().unwrap();
+        let quirks = Quirks::from_bits(reader.read_u8()?);
+        let seed = reader.read_u64()?;
+        let vip_random = match reader.read_bool()? {
+            true => Some(reader.read_bytes(256)?.try_into().unwrap()),
+            false => None,
+        };
+        let instructions_per_frame = reader.read_u32()?;
+        let stack_limit = reader.read_u32()? as usize;
+        let strict = reader.read_bool()?;
+        let frame_count = reader.read_u32()?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            frames.push(reader.read_u16()?);
+        }
+        if !reader.is_empty() {
+            return Err(MovieError::Corrupt);
+        }
+
+        Ok(Movie {
+            mode,
+            rom_hash,
+            quirks,
+            seed,
+            vip_random,
+            instructions_per_frame,
+            stack_limit,
+            strict,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Waits for a key, then stores a random byte in v1 and loops
+    const ROM: [u8; 6] = [0xF0, 0x0A, 0xC1, 0xFF, 0x12, 0x04];
+
+    fn record_run(seed: u64) -> (Movie, Vec<u8>) {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&ROM).unwrap();
+        processor.set_seed(seed);
+        let mut emulator = Emulator::new(processor);
+        let mut movie = Movie::new(&emulator);
+
+        for frame in 0..10 {
+            let mut keys = [false; 16];
+            keys[0xA] = frame == 5;
+            movie.record(&keys);
+            emulator.run_frame(&keys);
+        }
+        (movie, emulator.get_processor().save_state())
+    }
+
+    #[test]
+    fn replays_exactly() {
+        let (movie, state) = record_run(99);
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        let mut emulator = movie.create_emulator(&ROM).unwrap();
+        let mut frame = 0;
+        while let Some(keys) = movie.get_keys(frame) {
+            emulator.run_frame(&keys);
+            frame += 1;
+        }
+        assert_eq!(emulator.get_processor().save_state(), state);
+        assert_eq!(emulator.get_processor().get_registers()[0], 0xA);
+    }
+
+    #[test]
+    fn rejects_other_rom() {
+        let (movie, _) = record_run(1);
+        assert_eq!(movie.create_emulator(&[0x12, 0x00]).err(), Some(MovieError::RomMismatch));
+        assert_eq!(Movie::from_bytes(b"C8ST").err(), Some(MovieError::BadMagic));
+    }
+
+    #[test]
+    fn keeps_stack_limit_and_strict() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&ROM).unwrap();
+        processor.set_stack_limit(12);
+        processor.set_strict(true);
+        let movie = Movie::new(&Emulator::new(processor));
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let emulator = movie.create_emulator(&ROM).unwrap();
+        assert_eq!(emulator.get_processor().get_stack_limit(), 12);
+        assert!(emulator.get_processor().is_strict());
+    }
+}
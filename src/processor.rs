@@ -1,51 +1,296 @@
-use crate::io::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
-use crate::font::FONT;
-use crate::splash::SPLASH;
-use rand::prelude::*;
+use crate::config::ProcessorMode;
+use crate::instruction::Instruction;
+use crate::font::{BIG_FONT, BIG_FONT_ADDRESS, FONT, FONT_ADDRESS};
+use crate::quirks::{Quirks, QuirksProfile};
+use crate::random::Random;
+use crate::rom;
+use crate::state::{self, StateError, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+
+/// What happened during a successfully executed instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepOutcome {
+    Executed,
+    VramChanged,
+    /// The program executed 00FD and no longer runs.
+    Exited,
+}
+
+/// A fault that stops the program, instead of crashing the emulator.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExecError {
+    StackUnderflow,
+    StackOverflow,
+    MemoryOutOfBounds { addr: usize },
+    InvalidKey { key: u8 },
+    UnknownOpcode { pc: usize, opcode: u16 },
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::StackUnderflow => write!(f, "return with an empty stack"),
+            ExecError::StackOverflow => write!(f, "stack overflow"),
+            ExecError::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds at {addr:#06x}"),
+            ExecError::InvalidKey { key } => write!(f, "invalid key {key:#04x}"),
+            ExecError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {opcode:#06x} at {pc:#06x}"),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// A rom that doesn't fit in the memory of the mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RomTooLarge {
+    pub size: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for RomTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the rom is {} bytes, but only {} fit in memory in this mode", self.size, self.capacity)
+    }
+}
+
+impl std::error::Error for RomTooLarge {}
 
 pub struct Processor {
+    mode: ProcessorMode,
+    quirks: Quirks,
     ram: Vec<u8>,
-    framebuffer: Vec<Vec<bool>>,
+    framebuffer: Vec<Vec<u8>>,
+    planes: u8,
+    hires: bool,
+    exited: bool,
     pc: usize,
     i: usize,
     stack: Vec<usize>,
+    stack_limit: usize,
     delay_timer: u8,
     sound_timer: u8,
     registers: Vec<u8>,
-    rng: ThreadRng,
+    rpl_flags: [u8; 16],
+    audio_pattern: [u8; 16],
+    // Set once F002 has run, until then the buzzer plays the plain beep
+    audio_pattern_loaded: bool,
+    pitch: u8,
+    rng: Random,
     keys: [bool; 16],
+    strict: bool,
+    unknown_opcode_count: u64,
+    unknown_opcode_addresses: HashSet<usize>,
+    // SHA-256 of the loaded rom, stored in save states
+    rom_hash: [u8; 32],
 }
 
-impl Processor {
-    pub fn new() -> Processor {
-        let mut ram = vec![0u8; 4096];
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new(ProcessorMode::default())
+    }
+}
 
-        for i in 0..FONT.len() {
-            ram[i] = FONT[i];
-        }
+impl Processor {
+    pub fn new(mode: ProcessorMode) -> Processor {
+        let ram_size = match mode {
+            ProcessorMode::Chip8 | ProcessorMode::SuperChip => 0x1000,
+            ProcessorMode::XOChip => 0x10000,
+        };
+        let mut ram = vec![0u8; ram_size];
 
-        for i in 0..SPLASH.len() {
-            ram[i+80] = SPLASH[i];
-        }
+        ram[FONT_ADDRESS..FONT_ADDRESS + FONT.len()].copy_from_slice(&FONT);
+        ram[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
 
         Processor {
+            mode,
+            quirks: QuirksProfile::for_mode(mode).quirks(),
             ram,
-            framebuffer: vec![vec![false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            framebuffer: vec![vec![0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            planes: 1,
+            hires: false,
+            exited: false,
             pc: 0x200,
             i: 0,
             stack: Vec::new(),
+            stack_limit: match mode {
+                ProcessorMode::Chip8 => 12,
+                ProcessorMode::SuperChip | ProcessorMode::XOChip => 16,
+            },
             delay_timer: 0,
             sound_timer: 0,
             registers: vec![0u8; 16],
-            rng: thread_rng(),
+            rpl_flags: [0u8; 16],
+            audio_pattern: [0u8; 16],
+            audio_pattern_loaded: false,
+            pitch: 64,
+            rng: Random::from_entropy(),
             keys: [false; 16],
+            strict: false,
+            unknown_opcode_count: 0,
+            unknown_opcode_addresses: HashSet::new(),
+            rom_hash: rom::hash(&[]),
         }
     }
 
-    pub fn load_data(&mut self, data: &[u8]) {
-        for (i, val) in data.iter().enumerate() {
-            self.ram[0x200 + i] = *val;
+    pub fn load_data(&mut self, data: &[u8]) -> Result<(), RomTooLarge> {
+        let capacity = self.ram.len() - 0x200;
+        if data.len() > capacity {
+            return Err(RomTooLarge { size: data.len(), capacity });
+        }
+        self.ram[0x200..0x200 + data.len()].copy_from_slice(data);
+        self.rom_hash = rom::hash(data);
+        Ok(())
+    }
+
+    /// Restarts the random number generator from `seed`, making CXNN
+    /// reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+    }
+
+    /// The seed the random number generator started from.
+    pub fn get_seed(&self) -> u64 {
+        self.rng.get_seed()
+    }
+
+    /// Makes CXNN use the COSMAC VIP random routine, reading from `page`, the
+    /// bytes 0x100 to 0x1FF of the VIP interpreter. `None` selects the seeded
+    /// generator.
+    pub fn set_vip_random(&mut self, page: Option<[u8; 256]>) {
+        self.rng.set_vip_page(page);
+    }
+
+    pub fn get_vip_random(&self) -> Option<&[u8; 256]> {
+        self.rng.get_vip_page()
+    }
+
+    /// The SHA-256 hash of the rom passed to `load_data`.
+    pub fn get_rom_hash(&self) -> &[u8; 32] {
+        &self.rom_hash
+    }
+
+    /// Serializes the complete machine state: memory, display, registers,
+    /// stack, timers, keys and the random number generator. Settings like quirks are not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(STATE_MAGIC);
+        writer.write_u8(STATE_VERSION);
+        writer.write_u8(state::mode_to_byte(self.mode));
+        writer.write_bytes(&self.rom_hash);
+
+        writer.write_bytes(&self.ram);
+        writer.write_bool(self.hires);
+        for row in &self.framebuffer {
+            writer.write_bytes(row);
+        }
+        writer.write_u8(self.planes);
+        writer.write_bool(self.exited);
+        writer.write_u32(self.pc as u32);
+        writer.write_u32(self.i as u32);
+        writer.write_u8(self.stack.len() as u8);
+        for address in &self.stack {
+            writer.write_u32(*address as u32);
+        }
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_bytes(&self.registers);
+        writer.write_bytes(&self.rpl_flags);
+        writer.write_bytes(&self.audio_pattern);
+        writer.write_bool(self.audio_pattern_loaded);
+        writer.write_u8(self.pitch);
+        writer.write_u16(state::keys_to_bits(&self.keys));
+        writer.write_u64(self.rng.get_seed());
+        writer.write_u64(self.rng.get_state());
+        writer.write_bool(self.rng.get_vip_page().is_some());
+        if let Some(page) = self.rng.get_vip_page() {
+            writer.write_bytes(page);
+        }
+
+        writer.into_bytes()
+    }
+
+    /// Restores a state written by `save_state`. The state has to match the
+    /// mode of the processor and the loaded rom. On error the processor is
+    /// left unchanged.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(STATE_MAGIC.len()).ok() != Some(STATE_MAGIC.as_slice()) {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mode = state::mode_from_byte(reader.read_u8()?).ok_or(StateError::Corrupt)?;
+        if mode != self.mode {
+            return Err(StateError::ModeMismatch { expected: self.mode, found: mode });
+        }
+        if reader.read_bytes(32)? != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        let ram = reader.read_bytes(self.ram.len())?.to_vec();
+        let hires = reader.read_bool()?;
+        let (width, height) = if hires {
+            (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        };
+        let mut framebuffer = Vec::with_capacity(height);
+        for _ in 0..height {
+            framebuffer.push(reader.read_bytes(width)?.to_vec());
+        }
+        let planes = reader.read_u8()?;
+        let exited = reader.read_bool()?;
+        let pc = reader.read_u32()? as usize;
+        let i = reader.read_u32()? as usize;
+        let mut stack = Vec::new();
+        for _ in 0..reader.read_u8()? {
+            stack.push(reader.read_u32()? as usize);
+        }
+        let delay_timer = reader.read_u8()?;
+        let sound_timer = reader.read_u8()?;
+        let registers = reader.read_bytes(16)?.to_vec();
+        let rpl_flags = reader.read_bytes(16)?.try_into().unwrap();
+        let audio_pattern = reader.read_bytes(16)?.try_into().unwrap();
+        let audio_pattern_loaded = reader.read_bool()?;
+        let pitch = reader.read_u8()?;
+        let keys = reader.read_u16()?;
+        let mut rng = Random::new(reader.read_u64()?);
+        let rng_state = reader.read_u64()?;
+        if reader.read_bool()? {
+            rng.set_vip_page(Some(reader.read_bytes(256)?.try_into().unwrap()));
         }
+        rng.set_state(rng_state);
+        if !reader.is_empty() || pc >= self.ram.len() {
+            return Err(StateError::Corrupt);
+        }
+
+        self.ram = ram;
+        self.hires = hires;
+        self.framebuffer = framebuffer;
+        self.planes = planes;
+        self.exited = exited;
+        self.pc = pc;
+        self.i = i;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = audio_pattern;
+        self.audio_pattern_loaded = audio_pattern_loaded;
+        self.pitch = pitch;
+        self.rng = rng;
+        self.keys = state::keys_from_bits(keys);
+        Ok(())
     }
 
     pub fn set_keys(&mut self, keys: &[bool; 16]) {
@@ -66,8 +311,119 @@ impl Processor {
         &self.sound_timer
     }
 
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.i
+    }
+
+    pub fn set_index(&mut self, i: usize) {
+        self.i = i;
+    }
+
+    pub fn get_registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Sets register VX.
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    pub fn get_stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Overwrites memory from `addr` on, for debuggers.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), ExecError> {
+        let end = addr.checked_add(bytes.len()).ok_or(ExecError::MemoryOutOfBounds { addr })?;
+        let target = self.ram.get_mut(addr..end).ok_or(ExecError::MemoryOutOfBounds { addr })?;
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// The 16-byte 1-bit XO-CHIP audio pattern loaded by F002.
+    pub fn get_audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// True once the program has loaded an audio pattern.
+    pub fn has_audio_pattern(&self) -> bool {
+        self.audio_pattern_loaded
+    }
+
+    /// The XO-CHIP playback pitch set by FX3A.
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn get_mode(&self) -> ProcessorMode {
+        self.mode
+    }
+
+    pub fn get_quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Sets how many nested subroutine calls fit on the stack. The COSMAC VIP
+    /// interpreter had room for 12, later interpreters for 16.
+    pub fn set_stack_limit(&mut self, stack_limit: usize) {
+        self.stack_limit = stack_limit;
+    }
+
+    pub fn get_stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    /// In strict mode an unknown opcode stops the program with
+    /// `ExecError::UnknownOpcode`. Otherwise it is skipped and logged once
+    /// per address.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// The number of unknown opcodes encountered since the processor was created.
+    pub fn get_unknown_opcode_count(&self) -> u64 {
+        self.unknown_opcode_count
+    }
+
     pub fn reset(&mut self) {
-        self.framebuffer = vec![vec![false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        self.set_hires(false);
+        self.planes = 1;
+        self.audio_pattern = [0u8; 16];
+        self.audio_pattern_loaded = false;
+        self.pitch = 64;
+        self.exited = false;
         self.pc = 0x200;
         self.i = 0;
         self.stack = Vec::new();
@@ -76,83 +432,227 @@ impl Processor {
         self.registers = vec![0u8; 16];
     }
 
-    pub fn step(&mut self) -> bool {
-        let mut vram_changed = false;
+    /// Returns the width and height of the display in the current resolution.
+    pub fn get_resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        }
+    }
+
+    /// True once the program has executed 00FD (SUPER-CHIP exit).
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let (width, height) = self.get_resolution();
+        self.framebuffer = vec![vec![0; width]; height];
+    }
+
+    fn supports_superchip(&self) -> bool {
+        self.mode != ProcessorMode::Chip8
+    }
+
+    fn supports_xochip(&self) -> bool {
+        self.mode == ProcessorMode::XOChip
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, ExecError> {
+        self.ram.get(addr).copied().ok_or(ExecError::MemoryOutOfBounds { addr })
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u16, ExecError> {
+        Ok((self.read(addr)? as u16) << 8 | self.read(addr + 1)? as u16)
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), ExecError> {
+        match self.ram.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(ExecError::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    fn is_key_pressed(&self, key: u8) -> Result<bool, ExecError> {
+        self.keys.get(key as usize).copied().ok_or(ExecError::InvalidKey { key })
+    }
+
+    /// Skips the next instruction, which is four bytes long if it is the
+    /// XO-CHIP long index load F000 NNNN.
+    fn skip_if(&mut self, condition: bool) -> Result<(), ExecError> {
+        if condition {
+            let next = self.read_word(self.pc)?;
+            self.pc += if next == 0xF000 && self.supports_xochip() { 4 } else { 2 };
+        }
+        Ok(())
+    }
+
+    /// Moves the selected planes by dx pixels to the right and dy pixels
+    /// down, filling the uncovered area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.get_resolution();
+        let source = self.framebuffer.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let moved = if (0..width as isize).contains(&src_x) && (0..height as isize).contains(&src_y) {
+                    source[src_y as usize][src_x as usize]
+                } else {
+                    0
+                };
+                self.framebuffer[y][x] = (source[y][x] & !self.planes) | (moved & self.planes);
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> Result<StepOutcome, ExecError> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
 
         // Fetch instruction
-        let instruction = (self.ram[self.pc] as u16) << 8 | self.ram[self.pc + 1] as u16;
-        // println!("{:#04x}", instruction);
+        let instruction_pc = self.pc;
+        let opcode = self.read_word(self.pc)?;
         self.pc += 2;
 
-        // Turn into nibbles for identifying commands
-        let nibbles = (
-            ((instruction & 0xF000) >> 12) as u8,
-            ((instruction & 0x0F00) >> 8) as u8,
-            ((instruction & 0x00F0) >> 4) as u8,
-            (instruction & 0x000F) as u8,
-        );
-
-        match nibbles {
-            (0x0, 0x0, 0xe, 0x0) => {
-                for i in 0..self.framebuffer.len() {
-                    for j in 0..self.framebuffer[i].len() {
-                        self.framebuffer[i][j] = false;
+        let instruction = match Instruction::decode(opcode) {
+            Some(instruction) if instruction.is_supported(self.mode) => instruction,
+            _ => {
+                self.unknown_opcode_count += 1;
+                if self.strict {
+                    return Err(ExecError::UnknownOpcode { pc: instruction_pc, opcode });
+                }
+                if self.unknown_opcode_addresses.insert(instruction_pc) {
+                    println!("WARNING Skipped unknown opcode {opcode:#06x} at {instruction_pc:#06x}");
+                }
+                return Ok(StepOutcome::Executed);
+            }
+        };
+
+        let vram_changed = self.execute(instruction)?;
+
+        if self.exited {
+            Ok(StepOutcome::Exited)
+        } else if vram_changed {
+            Ok(StepOutcome::VramChanged)
+        } else {
+            Ok(StepOutcome::Executed)
+        }
+    }
+
+    /// Executes a decoded instruction, with the program counter already
+    /// pointing past it. Returns whether the framebuffer changed.
+    fn execute(&mut self, instruction: Instruction) -> Result<bool, ExecError> {
+        let mut vram_changed = false;
+
+        match instruction {
+            Instruction::ScrollDown(n) => {
+                self.scroll(0, n as isize);
+                vram_changed = true;
+            }
+            Instruction::ScrollUp(n) => {
+                self.scroll(0, -(n as isize));
+                vram_changed = true;
+            }
+            Instruction::ClearScreen => {
+                for row in self.framebuffer.iter_mut() {
+                    for pixel in row.iter_mut() {
+                        *pixel &= !self.planes;
                     }
                 }
                 vram_changed = true;
             }
-            (0x0, 0x0, 0xE, 0xE) => {
-                self.pc = self.stack.pop().unwrap();
+            Instruction::Return => {
+                self.pc = self.stack.pop().ok_or(ExecError::StackUnderflow)?;
+            }
+            Instruction::ScrollRight => {
+                self.scroll(4, 0);
+                vram_changed = true;
+            }
+            Instruction::ScrollLeft => {
+                self.scroll(-4, 0);
+                vram_changed = true;
             }
-            (0x1, ..) => {
-                self.pc = (instruction & 0x0FFF) as usize;
+            Instruction::Exit => {
+                self.exited = true;
             }
-            (0x2, ..) => {
-                self.stack.push(self.pc);
-                self.pc = (instruction & 0x0FFF) as usize;
+            Instruction::LoRes => {
+                self.set_hires(false);
+                vram_changed = true;
             }
-            (0x3, ..) => {
-                if self.registers[nibbles.1 as usize] == (instruction & 0xFF) as u8 {
-                    self.pc += 2;
+            Instruction::HiRes => {
+                self.set_hires(true);
+                vram_changed = true;
+            }
+            Instruction::Jump(nnn) => {
+                self.pc = nnn as usize;
+            }
+            Instruction::Call(nnn) => {
+                if self.stack.len() >= self.stack_limit {
+                    return Err(ExecError::StackOverflow);
                 }
+                self.stack.push(self.pc);
+                self.pc = nnn as usize;
+            }
+            Instruction::SkipIfEqualImmediate(x, nn) => {
+                self.skip_if(self.registers[x as usize] == nn)?;
+            }
+            Instruction::SkipIfNotEqualImmediate(x, nn) => {
+                self.skip_if(self.registers[x as usize] != nn)?;
             }
-            (0x4, ..) => {
-                if self.registers[nibbles.1 as usize] != (instruction & 0xFF) as u8 {
-                    self.pc += 2;
+            Instruction::SkipIfEqual(x, y) => {
+                self.skip_if(self.registers[x as usize] == self.registers[y as usize])?;
+            }
+            Instruction::SaveRange(x, y) => {
+                // Save vx..vy (in either order) to memory at I without changing I
+                for (offset, register) in Self::register_range(x as usize, y as usize).into_iter().enumerate() {
+                    self.write(self.i + offset, self.registers[register])?;
                 }
             }
-            (0x5, _, _, 0) => {
-                if self.registers[nibbles.1 as usize] == self.registers[nibbles.2 as usize] {
-                    self.pc += 2;
+            Instruction::LoadRange(x, y) => {
+                for (offset, register) in Self::register_range(x as usize, y as usize).into_iter().enumerate() {
+                    self.registers[register] = self.read(self.i + offset)?;
                 }
             }
-            (0x6, ..) => {
-                self.registers[nibbles.1 as usize] = (instruction & 0x00FF) as u8;
+            Instruction::SetImmediate(x, nn) => {
+                self.registers[x as usize] = nn;
             }
-            (0x7, ..) => {
-                self.registers[nibbles.1 as usize] =
-                    self.registers[nibbles.1 as usize].wrapping_add((instruction & 0x00FF) as u8);
+            Instruction::AddImmediate(x, nn) => {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(nn);
             }
-            (0x8, _, _, 0) => {
-                self.registers[nibbles.1 as usize] = self.registers[nibbles.2 as usize];
+            Instruction::Set(x, y) => {
+                self.registers[x as usize] = self.registers[y as usize];
             }
-            (0x8, _, _, 1) => {
-                self.registers[nibbles.1 as usize] |= self.registers[nibbles.2 as usize];
-                self.registers[0xF] = 0;
+            Instruction::Or(x, y) => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
-            (0x8, _, _, 2) => {
-                self.registers[nibbles.1 as usize] &= self.registers[nibbles.2 as usize];
-                self.registers[0xF] = 0;
+            Instruction::And(x, y) => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
-            (0x8, _, _, 3) => {
-                self.registers[nibbles.1 as usize] ^= self.registers[nibbles.2 as usize];
-                self.registers[0xF] = 0;
+            Instruction::Xor(x, y) => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
-            (0x8, _, _, 4) => {
+            Instruction::Add(x, y) => {
                 let overflow;
 
-                (self.registers[nibbles.1 as usize], overflow) = self.registers[nibbles.1 as usize]
-                    .overflowing_add(self.registers[nibbles.2 as usize]);
+                (self.registers[x as usize], overflow) =
+                    self.registers[x as usize].overflowing_add(self.registers[y as usize]);
 
                 if overflow {
                     self.registers[0xF] = 1;
@@ -160,19 +660,13 @@ impl Processor {
                     self.registers[0xF] = 0;
                 }
             }
-            (0x8, _, _, 5) => {
-                let x = self.registers[nibbles.1 as usize];
-                let y = self.registers[nibbles.2 as usize];
+            Instruction::Sub(x, y) => {
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
 
-                let overflow;
+                let overflow = vy > vx;
 
-                if y > x {
-                    overflow = true;
-                } else {
-                    overflow = false;
-                }
-
-                self.registers[nibbles.1 as usize] = x.wrapping_sub(y);
+                self.registers[x as usize] = vx.wrapping_sub(vy);
 
                 if overflow {
                     self.registers[0xF] = 0;
@@ -180,19 +674,13 @@ impl Processor {
                     self.registers[0xF] = 1;
                 }
             }
-            (0x8, _, _, 0x7) => {
-                let x = self.registers[nibbles.1 as usize];
-                let y = self.registers[nibbles.2 as usize];
-
-                let overflow;
+            Instruction::SubReverse(x, y) => {
+                let vx = self.registers[x as usize];
+                let vy = self.registers[y as usize];
 
-                if x > y {
-                    overflow = true;
-                } else {
-                    overflow = false;
-                }
+                let overflow = vx > vy;
 
-                self.registers[nibbles.1 as usize] = y.wrapping_sub(x);
+                self.registers[x as usize] = vy.wrapping_sub(vx);
 
                 if overflow {
                     self.registers[0xF] = 0;
@@ -200,133 +688,233 @@ impl Processor {
                     self.registers[0xF] = 1;
                 }
             }
-            (0x8, _, _, 0x6) => {
-                let y = self.registers[nibbles.2 as usize];
-                self.registers[nibbles.1 as usize] = y >> 1;
-                self.registers[0xF] = y & 1;
+            Instruction::ShiftRight(x, y) => {
+                let value = self.registers[self.shift_source(x, y)];
+                self.registers[x as usize] = value >> 1;
+                self.registers[0xF] = value & 1;
             }
-            (0x8, _, _, 0xE) => {
-                let y = self.registers[nibbles.2 as usize];
-                self.registers[nibbles.1 as usize] = y << 1;
-                self.registers[0xF] = (y >> 7) & 1;
+            Instruction::ShiftLeft(x, y) => {
+                let value = self.registers[self.shift_source(x, y)];
+                self.registers[x as usize] = value << 1;
+                self.registers[0xF] = (value >> 7) & 1;
             }
-            (0x9, _, _, 0) => {
-                if self.registers[nibbles.1 as usize] != self.registers[nibbles.2 as usize] {
-                    self.pc += 2;
-                }
+            Instruction::SkipIfNotEqual(x, y) => {
+                self.skip_if(self.registers[x as usize] != self.registers[y as usize])?;
             }
-            (0xA, ..) => {
-                self.i = (instruction & 0x0FFF) as usize;
+            Instruction::SetIndex(nnn) => {
+                self.i = nnn as usize;
             }
-            (0xB, ..) => {
-                self.pc = (instruction & 0x0FFF) as usize + self.registers[0] as usize;
+            Instruction::JumpWithOffset(nnn) => {
+                let offset_register = if self.quirks.jump_with_vx { (nnn >> 8) as usize } else { 0 };
+                self.pc = nnn as usize + self.registers[offset_register] as usize;
             }
-            (0xC, ..) => {
-                self.registers[nibbles.1 as usize] = (instruction & 0xFF) as u8 & self.rng.gen::<u8>();
+            Instruction::Random(x, nn) => {
+                self.registers[x as usize] = nn & self.rng.next_u8();
             }
-            (0xD, ..) => {
+            Instruction::Draw(x, y, n) => {
                 self.registers[0xF] = 0;
 
-                let x_reg = nibbles.1 as usize;
-                let y_reg = nibbles.2 as usize;
-                let mut y = (self.registers[y_reg] as usize) % DISPLAY_HEIGHT;
-
-                for byte in 0..nibbles.3 as usize {
-                    if y >= self.framebuffer.len() {
-                        break;
+                let (width, height) = self.get_resolution();
+                let x_start = (self.registers[x as usize] as usize) % width;
+                let y_start = (self.registers[y as usize] as usize) % height;
+
+                // DXY0 draws a 16x16 sprite made of two bytes per row
+                let (sprite_width, sprite_height) = match n {
+                    0 if self.supports_superchip() => (16, 16),
+                    n => (8, n as usize),
+                };
+                let bytes_per_row = sprite_width / 8;
+
+                // Each selected plane takes its own sprite, stored one after the other
+                let mut address = self.i;
+                for plane in [1u8, 2u8] {
+                    if self.planes & plane == 0 {
+                        continue;
                     }
-                    let mut x = (self.registers[x_reg] as usize) % DISPLAY_WIDTH;
-                    for bit in 0..8 {
-                        if x >= self.framebuffer[y].len() {
-                            break;
+
+                    for row in 0..sprite_height {
+                        let mut y = y_start + row;
+                        if y >= height {
+                            if self.quirks.clipping {
+                                break;
+                            }
+                            y %= height;
                         }
-                        let val = (self.ram[self.i + byte] >> (7 - bit)) & 1 == 1;
-                        if self.framebuffer[y][x] && val {
-                            self.registers[0xF] = 1;
+                        for col in 0..sprite_width {
+                            let mut x = x_start + col;
+                            if x >= width {
+                                if self.quirks.clipping {
+                                    break;
+                                }
+                                x %= width;
+                            }
+                            let byte = self.read(address + row * bytes_per_row + col / 8)?;
+                            if (byte >> (7 - col % 8)) & 1 == 0 {
+                                continue;
+                            }
+                            if self.framebuffer[y][x] & plane != 0 {
+                                self.registers[0xF] = 1;
+                            }
+                            self.framebuffer[y][x] ^= plane;
                         }
-                        self.framebuffer[y][x] ^= val;
-                        x += 1;
                     }
-                    y += 1;
+                    address += sprite_height * bytes_per_row;
                 }
                 vram_changed = true;
             }
-            (0xE, _, 0x9, 0xE) => {
-                let key = self.registers[nibbles.1 as usize] as usize;
-                if self.keys[key] {
-                    self.pc += 2;
-                }
+            Instruction::SkipIfKey(x) => {
+                let key = self.registers[x as usize];
+                self.skip_if(self.is_key_pressed(key)?)?;
             }
-            (0xE, _, 0xA, 0x1) => {
-                let key = self.registers[nibbles.1 as usize] as usize;
-                if !self.keys[key] {
-                    self.pc += 2;
-                }
+            Instruction::SkipIfNotKey(x) => {
+                let key = self.registers[x as usize];
+                self.skip_if(!self.is_key_pressed(key)?)?;
             }
-            (0xF, _, 0x0, 0x7) => {
-                self.registers[nibbles.1 as usize] = self.delay_timer;
+            Instruction::LongIndex => {
+                self.i = self.read_word(self.pc)? as usize;
+                self.pc += 2;
             }
-            (0xF, _, 0x0, 0xA) => {
-                let mut pressed = false;
-                for i in 0..16 {
-                    if self.keys[i] {
-                        self.registers[nibbles.1 as usize] = i as u8;
-                        pressed = true;
-                        break;
-                    }
+            Instruction::SelectPlanes(n) => {
+                self.planes = n & 0x3;
+            }
+            Instruction::LoadAudio => {
+                for offset in 0..16 {
+                    self.audio_pattern[offset] = self.read(self.i + offset)?;
                 }
-
-                if !pressed {
-                    self.pc -= 2;
+                self.audio_pattern_loaded = true;
+            }
+            Instruction::GetDelay(x) => {
+                self.registers[x as usize] = self.delay_timer;
+            }
+            Instruction::WaitKey(x) => {
+                match self.keys.iter().position(|pressed| *pressed) {
+                    Some(key) => self.registers[x as usize] = key as u8,
+                    None => self.pc -= 2,
                 }
             }
-            (0xF, _, 0x1, 0x5) => {
-                self.delay_timer = self.registers[nibbles.1 as usize];
+            Instruction::SetDelay(x) => {
+                self.delay_timer = self.registers[x as usize];
             }
-            (0xF, _, 0x1, 0x8) => {
-                self.sound_timer = self.registers[nibbles.1 as usize];
+            Instruction::SetSound(x) => {
+                self.sound_timer = self.registers[x as usize];
             }
-            (0xF, _, 0x1, 0xE) => {
-                self.i += self.registers[nibbles.1 as usize] as usize;
-                if self.i >= 0x1000 {
-                    self.registers[0xF] = 1;
-                } else {
-                    self.registers[0xF] = 0;
+            Instruction::AddIndex(x) => {
+                self.i += self.registers[x as usize] as usize;
+                // VF tells whether I went past the end of memory
+                if self.quirks.index_overflow {
+                    if self.i >= self.ram.len() {
+                        self.registers[0xF] = 1;
+                    } else {
+                        self.registers[0xF] = 0;
+                    }
                 }
             }
-            (0xF, _, 0x2, 0x9) => {
-                self.i = self.registers[nibbles.1 as usize] as usize * 5;
+            Instruction::Font(x) => {
+                self.i = FONT_ADDRESS + (self.registers[x as usize] & 0xF) as usize * 5;
             }
-            (0xF, _, 0x3, 0x3) => {
-                let x = self.registers[nibbles.1 as usize];
+            Instruction::BigFont(x) => {
+                self.i = BIG_FONT_ADDRESS + (self.registers[x as usize] & 0xF) as usize * 10;
+            }
+            Instruction::Bcd(x) => {
+                let x = self.registers[x as usize];
                 let d1 = x / 100;
                 let d2 = x % 100 / 10;
                 let d3 = x % 10;
-                self.ram[self.i] = d1;
-                self.ram[self.i + 1] = d2;
-                self.ram[self.i + 2] = d3;
+                self.write(self.i, d1)?;
+                self.write(self.i + 1, d2)?;
+                self.write(self.i + 2, d3)?;
+            }
+            Instruction::SetPitch(x) => {
+                self.pitch = self.registers[x as usize];
             }
-            (0xF, _, 0x5, 0x5) => {
-                for offset in 0..=nibbles.1 as usize {
-                    self.ram[self.i + offset] = self.registers[offset];
+            Instruction::Store(x) => {
+                for offset in 0..=x as usize {
+                    self.write(self.i + offset, self.registers[offset])?;
+                }
+                if self.quirks.memory_increment {
+                    self.i += x as usize + 1;
+                } else if self.quirks.memory_increment_by_x {
+                    self.i += x as usize;
                 }
-                self.i += nibbles.1 as usize + 1;
             }
-            (0xF, _, 0x6, 0x5) => {
-                for offset in 0..=nibbles.1 as usize {
-                    self.registers[offset] = self.ram[self.i + offset];
+            Instruction::Load(x) => {
+                for offset in 0..=x as usize {
+                    self.registers[offset] = self.read(self.i + offset)?;
                 }
-                self.i += nibbles.1 as usize + 1;
+                if self.quirks.memory_increment {
+                    self.i += x as usize + 1;
+                } else if self.quirks.memory_increment_by_x {
+                    self.i += x as usize;
+                }
+            }
+            Instruction::SaveFlags(x) => {
+                let count = self.rpl_flag_count(x);
+                self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Instruction::LoadFlags(x) => {
+                let count = self.rpl_flag_count(x);
+                self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
             }
-            _ => {}
         }
 
-        return vram_changed;
+        Ok(vram_changed)
     }
 
-    pub fn get_framebuffer(&self) -> &Vec<Vec<bool>> {
+    /// Returns the framebuffer as rows of pixels, where each pixel holds the
+    /// bitmask of the planes that are lit at that position.
+    pub fn get_framebuffer(&self) -> &Vec<Vec<u8>> {
         &self.framebuffer
     }
+
+    /// The SHA-256 hash of the screen: its width and height as big endian
+    /// u16s followed by the plane bitmask of every pixel, row by row. It only
+    /// changes when the picture or resolution does.
+    pub fn framebuffer_hash(&self) -> [u8; 32] {
+        let height = self.framebuffer.len();
+        let width = self.framebuffer.first().map_or(0, |row| row.len());
+
+        let mut hasher = Sha256::new();
+        hasher.update((width as u16).to_be_bytes());
+        hasher.update((height as u16).to_be_bytes());
+        for row in &self.framebuffer {
+            hasher.update(row);
+        }
+        hasher.finalize().into()
+    }
+
+    /// The SHA-256 hash of `save_state`, which changes with any part of the
+    /// machine state but also with the version of the state format.
+    pub fn state_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.save_state()).into()
+    }
+
+    /// The register shifted by 8XY6/8XYE, which is VX with the shift quirk
+    /// and VY without.
+    fn shift_source(&self, x: u8, y: u8) -> usize {
+        if self.quirks.shift {
+            x as usize
+        } else {
+            y as usize
+        }
+    }
+
+    /// SUPER-CHIP only has 8 RPL flags, XO-CHIP extends them to 16.
+    fn rpl_flag_count(&self, x: u8) -> usize {
+        let count = x as usize + 1;
+        if self.supports_xochip() {
+            count
+        } else {
+            count.min(8)
+        }
+    }
+
+    fn register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -335,9 +923,9 @@ mod tests {
 
     #[test]
     fn loading_data() {
-        let mut processor = Processor::new();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
         let data: [u8; 3] = [0x1, 0x2, 0x3];
-        processor.load_data(&data);
+        processor.load_data(&data).unwrap();
 
         assert_eq!(processor.ram[0x200], 0x1);
         assert_eq!(processor.ram[0x201], 0x2);
@@ -346,249 +934,249 @@ mod tests {
 
     #[test]
     fn clear_screen() {
-        let mut processor: Processor = Processor::new();
-        processor.framebuffer[5][13] = true;
-        processor.framebuffer[8][4] = true;
-        processor.framebuffer[3][9] = true;
-        processor.load_data(&[0x0, 0xE0]);
-        assert!(processor.step());
+        let mut processor: Processor = Processor::new(ProcessorMode::Chip8);
+        processor.framebuffer[5][13] = 1;
+        processor.framebuffer[8][4] = 1;
+        processor.framebuffer[3][9] = 1;
+        processor.load_data(&[0x0, 0xE0]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
 
         for i in 0..processor.framebuffer.len() {
             for j in 0..processor.framebuffer.len() {
-                assert!(!processor.framebuffer[i][j]);
+                assert_eq!(processor.framebuffer[i][j], 0);
             }
         }
     }
 
     #[test]
     fn jump() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x14, 0x11]);
-        assert!(!processor.step());
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x14, 0x11]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::Executed));
         assert_eq!(processor.pc, 0x0411);
     }
 
     #[test]
     fn set_register_x() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x64, 0x3F]);
-        assert!(!processor.step());
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x64, 0x3F]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::Executed));
         assert_eq!(processor.registers[0x4], 0x3F);
     }
 
     #[test]
     fn add_value_to_register_x() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x68, 0x05, 0x78, 0xF1]);
-        assert!(!processor.step());
-        assert!(!processor.step());
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x68, 0x05, 0x78, 0xF1]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::Executed));
+        assert_eq!(processor.step(), Ok(StepOutcome::Executed));
         assert_eq!(processor.registers[0x8], 0xF6);
     }
 
     #[test]
     fn set_index_register() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0xA1, 0x23]);
-        assert!(!processor.step());
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0xA1, 0x23]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::Executed));
         assert_eq!(processor.i, 0x123);
     }
 
     #[test]
     fn test_draw() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x01, 0x61, 0x01, 0xA2, 0x08, 0xD0, 0x11, 0b10101010]);
-        processor.step();
-        processor.step();
-        processor.step();
-        processor.step();
-        assert!(processor.framebuffer[1][1]);
-        assert!(!processor.framebuffer[1][2]);
-        assert!(processor.framebuffer[1][3]);
-        assert!(!processor.framebuffer[1][4]);
-        assert!(processor.framebuffer[1][5]);
-        assert!(!processor.framebuffer[1][6]);
-        assert!(processor.framebuffer[1][7]);
-        assert!(!processor.framebuffer[1][8]);
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x01, 0x61, 0x01, 0xA2, 0x08, 0xD0, 0x11, 0b10101010]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.framebuffer[1][1], 1);
+        assert_eq!(processor.framebuffer[1][2], 0);
+        assert_eq!(processor.framebuffer[1][3], 1);
+        assert_eq!(processor.framebuffer[1][4], 0);
+        assert_eq!(processor.framebuffer[1][5], 1);
+        assert_eq!(processor.framebuffer[1][6], 0);
+        assert_eq!(processor.framebuffer[1][7], 1);
+        assert_eq!(processor.framebuffer[1][8], 0);
     }
 
     #[test]
     fn test_skip_x_equal() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x22, 0x30, 0x05, 0x30, 0x22]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x22, 0x30, 0x05, 0x30, 0x22]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x204);
-        processor.step();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x208);
     }
 
     #[test]
     fn test_skip_x_not_equal() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x22, 0x40, 0x22, 0x40, 0x05]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x22, 0x40, 0x22, 0x40, 0x05]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x204);
-        processor.step();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x208);
     }
 
     #[test]
     fn test_skip_x_y_equal() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x12, 0x61, 0x08, 0x50, 0x10, 0x61, 0x12, 0x50, 0x10]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x12, 0x61, 0x08, 0x50, 0x10, 0x61, 0x12, 0x50, 0x10]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x206);
-        processor.step();
-        processor.step();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x20C);
     }
 
     #[test]
     fn test_skip_x_y_not_equal() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x12, 0x61, 0x12, 0x90, 0x10, 0x61, 0xFF, 0x90, 0x10]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x12, 0x61, 0x12, 0x90, 0x10, 0x61, 0xFF, 0x90, 0x10]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x206);
-        processor.step();
-        processor.step();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x20C);
     }
 
     #[test]
     fn test_set_x_y() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x64, 0x12, 0x87, 0x40]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x64, 0x12, 0x87, 0x40]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0x7], 0x12);
     }
 
     #[test]
     fn test_or() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x11]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x11]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b11001010 | 0b00101010);
         assert_eq!(processor.registers[1], 0b00101010);
     }
 
     #[test]
     fn test_and() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x12]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x12]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b11001010 & 0b00101010);
         assert_eq!(processor.registers[1], 0b00101010);
     }
 
     #[test]
     fn test_xor() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x13]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0b11001010, 0x61, 0b00101010, 0x80, 0x13]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b11001010 ^ 0b00101010);
         assert_eq!(processor.registers[1], 0b00101010);
     }
 
     #[test]
     fn test_add_flag() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0xFE, 0x61, 0x01, 0x80, 0x14, 0x80, 0x14]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0xFE, 0x61, 0x01, 0x80, 0x14, 0x80, 0x14]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0xFF);
         assert_eq!(processor.registers[0xF], 0);
-        processor.step();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0);
         assert_eq!(processor.registers[0xF], 1);
     }
 
     #[test]
     fn test_subtract_y_from_x() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0xF, 0x61, 0xA, 0x80, 0x15, 0x80, 0x15]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0xF, 0x61, 0xA, 0x80, 0x15, 0x80, 0x15]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0x05);
         assert_eq!(processor.registers[0xF], 1);
-        processor.step();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 251);
         assert_eq!(processor.registers[0xF], 0);
     }
 
     #[test]
     fn test_subtract_x_from_y() {
-        let mut processor = Processor::new();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
         processor.load_data(&[
             0x60, 0xA, 0x61, 0xF, 0x80, 0x17, 0x60, 0x01, 0x61, 0x00, 0x80, 0x17,
-        ]);
-        processor.step();
-        processor.step();
-        processor.step();
+        ]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0x05);
         assert_eq!(processor.registers[0xF], 1);
-        processor.step();
-        processor.step();
-        processor.step();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0xFF);
         assert_eq!(processor.registers[0xF], 0);
     }
 
     #[test]
     fn test_shift_right() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x61, 0b01010101, 0x80, 0x16, 0x61, 0b10000000, 0x80, 0x16]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x61, 0b01010101, 0x80, 0x16, 0x61, 0b10000000, 0x80, 0x16]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b00101010);
         assert_eq!(processor.registers[0xF], 1);
-        processor.step();
-        processor.step();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b01000000);
         assert_eq!(processor.registers[0xF], 0);
     }
 
     #[test]
     fn test_shift_left() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x61, 0b01010101, 0x80, 0x1E, 0x61, 0b10000000, 0x80, 0x1E]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x61, 0b01010101, 0x80, 0x1E, 0x61, 0b10000000, 0x80, 0x1E]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0b01010101 << 1);
         assert_eq!(processor.registers[0xF], 0);
-        processor.step();
-        processor.step();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.registers[0], 0);
         assert_eq!(processor.registers[0xF], 1);
     }
 
     #[test]
     fn test_jump_with_offset() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x05, 0xB0, 0x20]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x05, 0xB0, 0x20]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x25);
     }
 
     #[test]
     fn test_subroutine() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x21, 0x23]);
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x21, 0x23]).unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.stack.len(), 1);
         assert_eq!(processor.pc, 0x123);
         assert_eq!(processor.stack[0], 0x202);
@@ -596,76 +1184,130 @@ mod tests {
 
     #[test]
     fn test_return_subroutine() {
-        let mut processor = Processor::new();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
         processor.ram[0x124] = 0x00;
         processor.ram[0x125] = 0xEE;
-        processor.load_data(&[0x21, 0x24]);
-        processor.step();
-        processor.step();
+        processor.load_data(&[0x21, 0x24]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.pc, 0x202);
         assert_eq!(processor.stack.len(), 0);
     }
 
     #[test]
     fn test_set_delay_timer() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x12, 0xF0, 0x15, 0x60, 0x45]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x12, 0xF0, 0x15, 0x60, 0x45]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.delay_timer, 0x12);
-        processor.step();
+        processor.update_timers();
+        processor.step().unwrap();
         assert_eq!(processor.delay_timer, 0x11);
     }
 
     #[test]
     fn test_get_delay_timer() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x12, 0xF0, 0x15, 0xF1, 0x07]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x12, 0xF0, 0x15, 0xF1, 0x07]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.delay_timer, 0x12);
-        processor.step();
+        processor.update_timers();
+        processor.step().unwrap();
         assert_eq!(processor.delay_timer, 0x11);
         assert_eq!(processor.registers[1], 0x11);
     }
 
     #[test]
     fn test_set_sound_timer() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x12, 0xF0, 0x18, 0x60, 0x45]);
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x12, 0xF0, 0x18, 0x60, 0x45]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.sound_timer, 0x12);
-        processor.step();
+        processor.update_timers();
+        processor.step().unwrap();
         assert_eq!(processor.sound_timer, 0x11);
     }
 
     #[test]
     fn test_add_to_index() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0xA1, 0x23, 0x60, 0x01, 0xF0, 0x1E]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0xA1, 0x23, 0x60, 0x01, 0xF0, 0x1E]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.i, 0x124);
     }
 
+    #[test]
+    fn test_add_to_index_overflow() {
+        let quirks = Quirks { index_overflow: true, ..Quirks::default() };
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(quirks);
+        processor.load_data(&[0xAF, 0xF0, 0x60, 0x20, 0xF0, 0x1E]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x1010);
+        assert_eq!(processor.registers[0xF], 1);
+
+        // Without the quirk VF is left alone
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0xAF, 0xF0, 0x60, 0x20, 0x6F, 0x05, 0xF0, 0x1E]).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x1010);
+        assert_eq!(processor.registers[0xF], 5);
+
+        // XO-CHIP addresses 64 KiB
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.set_quirks(quirks);
+        processor.load_data(&[0xF0, 0x00, 0x1F, 0xF0, 0x60, 0x20, 0x6F, 0x01, 0xF0, 0x1E]).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x2010);
+        assert_eq!(processor.registers[0xF], 0);
+
+        processor.load_data(&[0xF0, 0x00, 0xFF, 0xF0, 0x60, 0x20, 0xF0, 0x1E]).unwrap();
+        processor.set_pc(0x200);
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x10010);
+        assert_eq!(processor.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let rom = vec![0; 0x1000];
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        assert_eq!(processor.load_data(&rom), Err(RomTooLarge { size: 0x1000, capacity: 0xE00 }));
+        assert!(processor.load_data(&rom[..0xE00]).is_ok());
+
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        assert!(processor.load_data(&rom).is_ok());
+    }
+
     #[test]
     fn test_font_character() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x0D, 0xF0, 0x29]);
-        processor.step();
-        processor.step();
-        assert_eq!(processor.i, 0xD);
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x0D, 0xF0, 0x29]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.i, FONT_ADDRESS + 0xD * 5);
     }
 
     #[test]
     fn test_decimal_conversion() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x9C, 0xA5, 0x67, 0xF0, 0x33]);
-        processor.step();
-        processor.step();
-        processor.step();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x9C, 0xA5, 0x67, 0xF0, 0x33]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
         assert_eq!(processor.ram[0x567], 1);
         assert_eq!(processor.ram[0x568], 5);
         assert_eq!(processor.ram[0x569], 6);
@@ -673,10 +1315,10 @@ mod tests {
 
     #[test]
     fn test_store() {
-        let mut processor = Processor::new();
-        processor.load_data(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA5, 0x00, 0xF2, 0x55]);
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA5, 0x00, 0xF2, 0x55]).unwrap();
         for _ in 0..5 {
-            processor.step();
+            processor.step().unwrap();
         }
         assert_eq!(processor.ram[0x500], 0x01);
         assert_eq!(processor.ram[0x501], 0x02);
@@ -685,16 +1327,422 @@ mod tests {
 
     #[test]
     fn test_load() {
-        let mut processor = Processor::new();
+        let mut processor = Processor::new(ProcessorMode::Chip8);
         processor.ram[0x500] = 0x10;
         processor.ram[0x501] = 0x20;
         processor.ram[0x502] = 0x30;
-        processor.load_data(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA5, 0x00, 0xF2, 0x65]);
+        processor.load_data(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA5, 0x00, 0xF2, 0x65]).unwrap();
         for _ in 0..5 {
-            processor.step();
+            processor.step().unwrap();
         }
         assert_eq!(processor.registers[0], 0x10);
         assert_eq!(processor.registers[1], 0x20);
         assert_eq!(processor.registers[2], 0x30);
     }
+
+    #[test]
+    fn test_hires() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.load_data(&[0x00, 0xFF, 0x00, 0xFE]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.get_resolution(), (128, 64));
+        assert_eq!(processor.framebuffer.len(), 64);
+        assert_eq!(processor.framebuffer[0].len(), 128);
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.get_resolution(), (64, 32));
+        assert_eq!(processor.framebuffer.len(), 32);
+        assert_eq!(processor.framebuffer[0].len(), 64);
+    }
+
+    #[test]
+    fn test_scroll_down() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.framebuffer[0][3] = 1;
+        processor.load_data(&[0x00, 0xC2]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.framebuffer[0][3], 0);
+        assert_eq!(processor.framebuffer[2][3], 1);
+    }
+
+    #[test]
+    fn test_scroll_right() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.framebuffer[1][0] = 1;
+        processor.framebuffer[1][63] = 1;
+        processor.load_data(&[0x00, 0xFB]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.framebuffer[1][4], 1);
+        assert_eq!(processor.framebuffer[1][0], 0);
+        assert_eq!(processor.framebuffer[1][3], 0);
+    }
+
+    #[test]
+    fn test_scroll_left() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.framebuffer[1][4] = 1;
+        processor.framebuffer[1][0] = 1;
+        processor.load_data(&[0x00, 0xFC]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.framebuffer[1][0], 1);
+        assert_eq!(processor.framebuffer[1][63], 0);
+        assert_eq!(processor.framebuffer[1][4], 0);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.load_data(&[0x00, 0xFD, 0x60, 0x01]).unwrap();
+        processor.step().unwrap();
+        assert!(processor.has_exited());
+        processor.step().unwrap();
+        assert_eq!(processor.registers[0], 0);
+        assert_eq!(processor.pc, 0x202);
+    }
+
+    #[test]
+    fn test_draw_large_sprite() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        let mut data = vec![0x00, 0xFF, 0xA2, 0x06, 0xD0, 0x00];
+        data.extend_from_slice(&[0xFF; 32]);
+        processor.load_data(&data).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.framebuffer[0][0], 1);
+        assert_eq!(processor.framebuffer[15][15], 1);
+        assert_eq!(processor.framebuffer[16][16], 0);
+        assert_eq!(processor.registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_big_font_character() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.load_data(&[0x60, 0x07, 0xF0, 0x30]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.i, BIG_FONT_ADDRESS + 7 * 10);
+        assert_eq!(processor.ram[processor.i], 0xFF);
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.load_data(&[0x60, 0x11, 0x61, 0x22, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85]).unwrap();
+        for _ in 0..6 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.registers[0], 0x11);
+        assert_eq!(processor.registers[1], 0x22);
+    }
+
+    #[test]
+    fn test_save_register_range() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.load_data(&[0x61, 0x11, 0x62, 0x22, 0x63, 0x33, 0xA5, 0x00, 0x53, 0x12]).unwrap();
+        for _ in 0..5 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.ram[0x500], 0x33);
+        assert_eq!(processor.ram[0x501], 0x22);
+        assert_eq!(processor.ram[0x502], 0x11);
+        assert_eq!(processor.i, 0x500);
+    }
+
+    #[test]
+    fn test_load_register_range() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.ram[0x500] = 0x10;
+        processor.ram[0x501] = 0x20;
+        processor.load_data(&[0xA5, 0x00, 0x52, 0x33]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.registers[2], 0x10);
+        assert_eq!(processor.registers[3], 0x20);
+        assert_eq!(processor.i, 0x500);
+    }
+
+    #[test]
+    fn test_long_index() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.load_data(&[0xF0, 0x00, 0xAB, 0xCD]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.i, 0xABCD);
+        assert_eq!(processor.pc, 0x204);
+    }
+
+    #[test]
+    fn test_skip_long_index() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.load_data(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.pc, 0x206);
+    }
+
+    #[test]
+    fn test_draw_planes() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.load_data(&[0xF3, 0x01, 0xA2, 0x06, 0xD0, 0x01, 0x80, 0xC0]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.framebuffer[0][0], 0b11);
+        assert_eq!(processor.framebuffer[0][1], 0b10);
+        assert_eq!(processor.framebuffer[0][2], 0);
+    }
+
+    #[test]
+    fn test_clear_selected_plane() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.framebuffer[2][2] = 0b11;
+        processor.load_data(&[0xF2, 0x01, 0x00, 0xE0]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.framebuffer[2][2], 0b01);
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.framebuffer[5][3] = 1;
+        processor.load_data(&[0x00, 0xD2]).unwrap();
+        assert_eq!(processor.step(), Ok(StepOutcome::VramChanged));
+        assert_eq!(processor.framebuffer[5][3], 0);
+        assert_eq!(processor.framebuffer[3][3], 1);
+    }
+
+    #[test]
+    fn test_audio_pattern_and_pitch() {
+        let mut processor = Processor::new(ProcessorMode::XOChip);
+        processor.ram[0x500..0x510].copy_from_slice(&[0xAA; 16]);
+        processor.load_data(&[0xA5, 0x00, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A]).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        assert!(processor.has_audio_pattern());
+        assert_eq!(processor.get_audio_pattern(), &[0xAA; 16]);
+        assert_eq!(processor.get_pitch(), 0x70);
+    }
+
+    #[test]
+    fn test_mode_memory_size() {
+        assert_eq!(Processor::new(ProcessorMode::Chip8).ram.len(), 0x1000);
+        assert_eq!(Processor::new(ProcessorMode::SuperChip).ram.len(), 0x1000);
+        assert_eq!(Processor::new(ProcessorMode::XOChip).ram.len(), 0x10000);
+    }
+
+    #[test]
+    fn test_chip8_ignores_extensions() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_strict(true);
+        processor.load_data(&[0x00, 0xFF]).unwrap();
+        assert_eq!(processor.step(), Err(ExecError::UnknownOpcode { pc: 0x200, opcode: 0x00FF }));
+        assert_eq!(processor.get_resolution(), (64, 32));
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(QuirksProfile::SuperChip11.quirks());
+        processor.load_data(&[0x60, 0b00000110, 0x61, 0b01010101, 0x80, 0x16]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.registers[0], 0b00000011);
+        assert_eq!(processor.registers[0xF], 0);
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(QuirksProfile::XOChip.quirks());
+        processor.load_data(&[0x6F, 0x05, 0x80, 0x11]).unwrap();
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.registers[0xF], 0x05);
+    }
+
+    #[test]
+    fn test_memory_increment_quirk() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(QuirksProfile::SuperChip11.quirks());
+        processor.load_data(&[0xA5, 0x00, 0xF2, 0x55, 0xF2, 0x65]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x500);
+
+        processor.set_quirks(QuirksProfile::Chip48.quirks());
+        processor.set_pc(0x200);
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.i, 0x504);
+    }
+
+    #[test]
+    fn test_jump_with_vx_quirk() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(QuirksProfile::Chip48.quirks());
+        processor.load_data(&[0x60, 0x05, 0x62, 0x10, 0xB2, 0x20]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.pc, 0x230);
+    }
+
+    #[test]
+    fn test_wrapping_quirk() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_quirks(QuirksProfile::XOChip.quirks());
+        processor.load_data(&[0x60, 0x3E, 0x61, 0x1F, 0xA2, 0x08, 0xD0, 0x12, 0xF0, 0xF0]).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.framebuffer[31][62], 1);
+        assert_eq!(processor.framebuffer[31][1], 1);
+        assert_eq!(processor.framebuffer[0][63], 1);
+        assert_eq!(processor.framebuffer[0][0], 1);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x00, 0xEE]).unwrap();
+        assert_eq!(processor.step(), Err(ExecError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x22, 0x00]).unwrap();
+        for _ in 0..12 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.step(), Err(ExecError::StackOverflow));
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0xAF, 0xFF, 0xF0, 0x33]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.step(), Err(ExecError::MemoryOutOfBounds { addr: 0x1000 }));
+    }
+
+    #[test]
+    fn test_pc_out_of_bounds() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x1F, 0xFF]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.step(), Err(ExecError::MemoryOutOfBounds { addr: 0x1000 }));
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x60, 0x20, 0xE0, 0x9E]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.step(), Err(ExecError::InvalidKey { key: 0x20 }));
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_strict(true);
+        processor.load_data(&[0x60, 0x01, 0xFF, 0xFF]).unwrap();
+        processor.step().unwrap();
+        assert_eq!(processor.step(), Err(ExecError::UnknownOpcode { pc: 0x202, opcode: 0xFFFF }));
+    }
+
+    #[test]
+    fn test_skip_unknown_opcode() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x01, 0x23, 0x12, 0x00]).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        assert_eq!(processor.pc, 0x200);
+        assert_eq!(processor.get_unknown_opcode_count(), 2);
+        assert_eq!(processor.unknown_opcode_addresses.len(), 1);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        // hires, v0 := 0x42, call 0x20A, ..., sprite v0 v0 0 at 0x20A
+        let rom = [0x00, 0xFF, 0x60, 0x42, 0x22, 0x0A, 0x00, 0x00, 0x00, 0x00, 0xD0, 0x00];
+        let mut processor = Processor::new(ProcessorMode::SuperChip);
+        processor.load_data(&rom).unwrap();
+        for _ in 0..4 {
+            processor.step().unwrap();
+        }
+        let state = processor.save_state();
+
+        let mut restored = Processor::new(ProcessorMode::SuperChip);
+        restored.load_data(&rom).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.pc, processor.pc);
+        assert_eq!(restored.stack, vec![0x206]);
+        assert_eq!(restored.registers[0], 0x42);
+        assert_eq!(restored.get_resolution(), (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT));
+        assert_eq!(restored.framebuffer, processor.framebuffer);
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn test_load_state_checks_header() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.load_data(&[0x12, 0x00]).unwrap();
+        let mut state = processor.save_state();
+
+        let mut other_rom = Processor::new(ProcessorMode::Chip8);
+        other_rom.load_data(&[0x12, 0x02]).unwrap();
+        assert_eq!(other_rom.load_state(&state), Err(StateError::RomMismatch));
+
+        let mut other_mode = Processor::new(ProcessorMode::XOChip);
+        other_mode.load_data(&[0x12, 0x00]).unwrap();
+        assert!(matches!(other_mode.load_state(&state), Err(StateError::ModeMismatch { .. })));
+
+        state.pop();
+        assert_eq!(processor.load_state(&state), Err(StateError::Corrupt));
+        assert_eq!(processor.load_state(b"nope"), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let mut values = Vec::new();
+        for _ in 0..2 {
+            let mut processor = Processor::new(ProcessorMode::Chip8);
+            processor.set_seed(42);
+            processor.load_data(&[0xC0, 0xFF, 0xC1, 0xFF]).unwrap();
+            processor.step().unwrap();
+            processor.step().unwrap();
+            values.push((processor.registers[0], processor.registers[1]));
+        }
+        assert_eq!(values[0], values[1]);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        processor.set_seed(0);
+        // v0 := 0x12, i := hex v0, sprite v0 v0 5
+        let rom = [0x60, 0x12, 0xF0, 0x29, 0xD0, 0x05];
+        processor.load_data(&rom).unwrap();
+        let blank = processor.framebuffer_hash();
+        assert_eq!(blank, rom::hash(&[&[0, 64, 0, 32][..], &[0; 64 * 32]].concat()));
+
+        let state = processor.state_hash();
+        processor.step().unwrap();
+        assert_eq!(processor.framebuffer_hash(), blank);
+        assert_ne!(processor.state_hash(), state);
+
+        processor.step().unwrap();
+        processor.step().unwrap();
+        assert_ne!(processor.framebuffer_hash(), blank);
+        let mut copy = Processor::new(ProcessorMode::Chip8);
+        copy.load_data(&rom).unwrap();
+        copy.load_state(&processor.save_state()).unwrap();
+        assert_eq!(copy.framebuffer_hash(), processor.framebuffer_hash());
+        assert_eq!(copy.state_hash(), processor.state_hash());
+    }
 }
@@ -0,0 +1,217 @@
+use crate::config::ProcessorMode;
+
+/// Interpretations of the opcodes whose behavior differs between CHIP-8
+/// implementations.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing past the last register stored or loaded.
+    pub memory_increment: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to zero.
+    pub vf_reset: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_with_vx: bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// Drawing a sprite waits for the next frame.
+    pub display_wait: bool,
+    /// Without memory_increment, FX55/FX65 advance I by X instead of leaving it unchanged.
+    pub memory_increment_by_x: bool,
+    /// FX1E sets VF when I goes past the end of memory, as the Amiga interpreter did.
+    pub index_overflow: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuirksProfile {
+    CosmacVip,
+    Chip48,
+    SuperChip10,
+    SuperChip11,
+    XOChip,
+}
+
+impl QuirksProfile {
+    pub const ALL: [QuirksProfile; 5] = [
+        QuirksProfile::CosmacVip,
+        QuirksProfile::Chip48,
+        QuirksProfile::SuperChip10,
+        QuirksProfile::SuperChip11,
+        QuirksProfile::XOChip,
+    ];
+
+    /// The profile matching the platform a mode was designed for.
+    pub fn for_mode(mode: ProcessorMode) -> QuirksProfile {
+        match mode {
+            ProcessorMode::Chip8 => QuirksProfile::CosmacVip,
+            ProcessorMode::SuperChip => QuirksProfile::SuperChip11,
+            ProcessorMode::XOChip => QuirksProfile::XOChip,
+        }
+    }
+
+    /// Looks up a profile by the name used in config.ini.
+    pub fn from_name(name: &str) -> Option<QuirksProfile> {
+        Self::ALL.into_iter().find(|profile| profile.get_name() == name)
+    }
+
+    /// Finds the profile with exactly these quirks, if there is one.
+    pub fn matching(quirks: &Quirks) -> Option<QuirksProfile> {
+        Self::ALL.into_iter().find(|profile| profile.quirks() == *quirks)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            QuirksProfile::CosmacVip => "cosmac-vip",
+            QuirksProfile::Chip48 => "chip-48",
+            QuirksProfile::SuperChip10 => "schip-1.0",
+            QuirksProfile::SuperChip11 => "schip-1.1",
+            QuirksProfile::XOChip => "xo-chip",
+        }
+    }
+
+    pub fn get_title(&self) -> &'static str {
+        match self {
+            QuirksProfile::CosmacVip => "COSMAC VIP",
+            QuirksProfile::Chip48 => "CHIP-48",
+            QuirksProfile::SuperChip10 => "SUPER-CHIP 1.0",
+            QuirksProfile::SuperChip11 => "SUPER-CHIP 1.1",
+            QuirksProfile::XOChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirksProfile::CosmacVip => Quirks {
+                shift: false,
+                memory_increment: true,
+                vf_reset: true,
+                jump_with_vx: false,
+                clipping: true,
+                display_wait: true,
+                memory_increment_by_x: false,
+                index_overflow: false,
+            },
+            // CHIP-48 added X to I after FX55/FX65, one short of the VIP
+            QuirksProfile::Chip48 => Quirks {
+                shift: true,
+                memory_increment: false,
+                vf_reset: false,
+                jump_with_vx: true,
+                clipping: true,
+                display_wait: false,
+                memory_increment_by_x: true,
+                index_overflow: false,
+            },
+            // The original SUPER-CHIP kept that and waited for the display interrupt before drawing
+            QuirksProfile::SuperChip10 => Quirks {
+                shift: true,
+                memory_increment: false,
+                vf_reset: false,
+                jump_with_vx: true,
+                clipping: true,
+                display_wait: true,
+                memory_increment_by_x: true,
+                index_overflow: false,
+            },
+            QuirksProfile::SuperChip11 => Quirks {
+                shift: true,
+                memory_increment: false,
+                vf_reset: false,
+                jump_with_vx: true,
+                clipping: true,
+                display_wait: false,
+                memory_increment_by_x: false,
+                index_overflow: false,
+            },
+            QuirksProfile::XOChip => Quirks {
+                shift: false,
+                memory_increment: true,
+                vf_reset: false,
+                jump_with_vx: false,
+                clipping: false,
+                display_wait: false,
+                memory_increment_by_x: false,
+                index_overflow: false,
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        QuirksProfile::CosmacVip.quirks()
+    }
+}
+
+impl Quirks {
+    /// Packs the quirks into one byte, one bit each in declaration order.
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.shift,
+            self.memory_increment,
+            self.vf_reset,
+            self.jump_with_vx,
+            self.clipping,
+            self.display_wait,
+            self.memory_increment_by_x,
+            self.index_overflow,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, enabled)| bits | (*enabled as u8) << i)
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        Quirks {
+            shift: bits & 0x01 != 0,
+            memory_increment: bits & 0x02 != 0,
+            vf_reset: bits & 0x04 != 0,
+            jump_with_vx: bits & 0x08 != 0,
+            clipping: bits & 0x10 != 0,
+            display_wait: bits & 0x20 != 0,
+            memory_increment_by_x: bits & 0x40 != 0,
+            index_overflow: bits & 0x80 != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names() {
+        for profile in QuirksProfile::ALL {
+            assert_eq!(QuirksProfile::from_name(profile.get_name()), Some(profile));
+        }
+        assert_eq!(QuirksProfile::from_name("custom"), None);
+    }
+
+    #[test]
+    fn matching_profile() {
+        for profile in QuirksProfile::ALL {
+            assert_eq!(QuirksProfile::matching(&profile.quirks()), Some(profile));
+        }
+        let custom = Quirks { clipping: false, ..QuirksProfile::CosmacVip.quirks() };
+        assert_eq!(QuirksProfile::matching(&custom), None);
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for profile in QuirksProfile::ALL {
+            assert_eq!(Quirks::from_bits(profile.quirks().to_bits()), profile.quirks());
+        }
+        let custom = Quirks { shift: true, index_overflow: true, ..QuirksProfile::XOChip.quirks() };
+        assert_eq!(Quirks::from_bits(custom.to_bits()), custom);
+    }
+
+    #[test]
+    fn profiles_for_modes() {
+        assert_eq!(QuirksProfile::for_mode(ProcessorMode::Chip8), QuirksProfile::CosmacVip);
+        assert_eq!(QuirksProfile::for_mode(ProcessorMode::SuperChip), QuirksProfile::SuperChip11);
+        assert_eq!(QuirksProfile::for_mode(ProcessorMode::XOChip), QuirksProfile::XOChip);
+        assert_eq!(Quirks::default(), QuirksProfile::CosmacVip.quirks());
+        let quirks = QuirksProfile::for_mode(ProcessorMode::SuperChip).quirks();
+        assert_eq!(QuirksProfile::matching(&quirks), Some(QuirksProfile::SuperChip11));
+    }
+}
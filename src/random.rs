@@ -0,0 +1,156 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The size of a COSMAC VIP CHIP-8 interpreter image.
+pub const VIP_INTERPRETER_SIZE: usize = 0x200;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RandomMode {
+    /// A seeded SplitMix64 generator.
+    #[default]
+    Seeded,
+    /// The random routine of the COSMAC VIP interpreter.
+    CosmacVip,
+}
+
+impl RandomMode {
+    pub fn from_name(name: &str) -> Option<RandomMode> {
+        match name {
+            "seeded" => Some(RandomMode::Seeded),
+            "cosmac-vip" => Some(RandomMode::CosmacVip),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            RandomMode::Seeded => "seeded",
+            RandomMode::CosmacVip => "cosmac-vip",
+        }
+    }
+}
+
+/// The random number generator behind CXNN. It is seedable, so that runs can
+/// be reproduced, and its state can be stored in save states.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+    // The second page of the VIP interpreter, which its random routine reads
+    vip_page: Option<Box<[u8; 256]>>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { seed, state: seed, vip_page: None }
+    }
+
+    /// Seeds the generator from the operating system.
+    pub fn from_entropy() -> Random {
+        Random::new(rand::random())
+    }
+
+    /// Restarts the generator from `seed`, keeping its mode.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.state = seed;
+    }
+
+    /// The seed the generator started from.
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    pub fn get_mode(&self) -> RandomMode {
+        match self.vip_page {
+            Some(_) => RandomMode::CosmacVip,
+            None => RandomMode::Seeded,
+        }
+    }
+
+    /// Switches to the VIP random routine, which reads from `page`, the
+    /// interpreter bytes at 0x100 to 0x1FF. `None` switches back to the seeded
+    /// generator. Either way the generator restarts from its seed.
+    pub fn set_vip_page(&mut self, page: Option<[u8; 256]>) {
+        self.vip_page = page.map(Box::new);
+        self.state = self.seed;
+    }
+
+    pub fn get_vip_page(&self) -> Option<&[u8; 256]> {
+        self.vip_page.as_deref()
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        match &self.vip_page {
+            Some(page) => {
+                // The VIP keeps its seed in R9: it increments R9, adds the
+                // interpreter byte indexed by R9.0 to R9.1 and returns the
+                // sum, which also becomes the new R9.1
+                let r9 = (self.state as u16).wrapping_add(1);
+                let value = page[(r9 & 0xFF) as usize].wrapping_add((r9 >> 8) as u8);
+                self.state = ((value as u16) << 8 | (r9 & 0xFF)) as u64;
+                value
+            }
+            None => (self.next_u64() >> 56) as u8,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Reads the page the VIP random routine uses from a dump of the 512 byte
+/// CHIP-8 interpreter of the COSMAC VIP.
+pub fn load_vip_page(path: &Path) -> io::Result<[u8; 256]> {
+    let interpreter = fs::read(path)?;
+    if interpreter.len() < VIP_INTERPRETER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected a {VIP_INTERPRETER_SIZE} byte interpreter, got {} bytes", interpreter.len()),
+        ));
+    }
+    Ok(interpreter[0x100..0x200].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Random::new(1234);
+        let mut b = Random::new(1234);
+        let mut c = Random::new(4321);
+        let sequence: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+        assert_eq!(sequence, (0..16).map(|_| b.next_u8()).collect::<Vec<u8>>());
+        assert_ne!(sequence, (0..16).map(|_| c.next_u8()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn vip_routine() {
+        let mut page = [0u8; 256];
+        page[0x01] = 0x10;
+        page[0x02] = 0x05;
+        let mut random = Random::new(0);
+        random.set_vip_page(Some(page));
+
+        assert_eq!(random.get_mode(), RandomMode::CosmacVip);
+        assert_eq!(random.next_u8(), 0x10);
+        assert_eq!(random.next_u8(), 0x15);
+        assert_eq!(random.get_state(), 0x1502);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+/// Keeps the save states of the last frames so the program can run
+/// backwards. Only the newest state is stored in full, every older one as
+/// the difference to the state after it, because most of memory doesn't
+/// change from frame to frame.
+pub struct Rewind {
+    newest: Option<Vec<u8>>,
+    // Oldest first, applying the last delta to `newest` gives the state
+    // before it
+    deltas: VecDeque<Vec<u8>>,
+    max_frames: usize,
+    max_bytes: usize,
+    bytes: usize,
+}
+
+// The first byte of a delta
+const DELTA_XOR: u8 = 0;
+const DELTA_FULL: u8 = 1;
+
+impl Rewind {
+    /// Keeps up to `max_frames` frames, using no more than about `max_bytes`.
+    pub fn new(max_frames: usize, max_bytes: usize) -> Rewind {
+        Rewind {
+            newest: None,
+            deltas: VecDeque::new(),
+            max_frames,
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    /// Records the state after a frame.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            let delta = encode_delta(&state, &newest);
+            self.bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(state);
+
+        while self.deltas.len() >= self.max_frames.max(1) || (self.bytes > self.max_bytes && !self.deltas.is_empty()) {
+            let oldest = self.deltas.pop_front().unwrap();
+            self.bytes -= oldest.len();
+        }
+    }
+
+    /// Steps back one frame and returns the state recorded before the newest,
+    /// or `None` once the buffer holds no older frame.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        self.bytes -= delta.len();
+        let previous = decode_delta(self.newest.as_ref().unwrap(), &delta);
+        self.newest = Some(previous.clone());
+        Some(previous)
+    }
+
+    /// The number of frames that can be stepped back.
+    pub fn get_frames(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// The memory used by the older frames.
+    pub fn get_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Describes `old` relative to `new` as runs of changed bytes, each stored as
+/// the number of unchanged bytes before it, its length and its XOR with `new`.
+/// States of different size, after switching resolution, are stored in full.
+fn encode_delta(new: &[u8], old: &[u8]) -> Vec<u8> {
+    if new.len() != old.len() {
+        let mut delta = vec![DELTA_FULL];
+        delta.extend_from_slice(old);
+        return delta;
+    }
+
+    let mut delta = vec![DELTA_XOR];
+    let mut offset = 0;
+    while offset < new.len() {
+        let start = match (offset..new.len()).find(|i| new[*i] != old[*i]) {
+            Some(start) => start,
+            None => break,
+        };
+        let end = (start..new.len()).find(|i| new[*i] == old[*i]).unwrap_or(new.len());
+
+        delta.extend_from_slice(&((start - offset) as u32).to_le_bytes());
+        delta.extend_from_slice(&((end - start) as u32).to_le_bytes());
+        delta.extend(new[start..end].iter().zip(&old[start..end]).map(|(a, b)| a ^ b));
+        offset = end;
+    }
+    delta
+}
+
+fn decode_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == DELTA_FULL {
+        return delta[1..].to_vec();
+    }
+
+    let mut old = new.to_vec();
+    let mut offset = 0;
+    let mut position = 1;
+    while position < delta.len() {
+        let skip = u32::from_le_bytes(delta[position..position + 4].try_into().unwrap()) as usize;
+        let length = u32::from_le_bytes(delta[position + 4..position + 8].try_into().unwrap()) as usize;
+        position += 8;
+        offset += skip;
+        for (byte, xor) in old[offset..offset + length].iter_mut().zip(&delta[position..position + length]) {
+            *byte ^= xor;
+        }
+        offset += length;
+        position += length;
+    }
+    old
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_back_through_frames() {
+        let mut rewind = Rewind::new(10, 1024);
+        rewind.push(vec![0; 100]);
+        let mut second = vec![0; 100];
+        second[5] = 1;
+        second[50] = 2;
+        rewind.push(second.clone());
+        rewind.push(vec![9; 20]);
+
+        assert_eq!(rewind.get_frames(), 2);
+        assert_eq!(rewind.pop(), Some(second));
+        assert_eq!(rewind.pop(), Some(vec![0; 100]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn small_deltas() {
+        let old = vec![0u8; 4096];
+        let mut new = old.clone();
+        new[0x200] = 0x12;
+        let delta = encode_delta(&new, &old);
+        assert_eq!(delta.len(), 1 + 8 + 1);
+        assert_eq!(decode_delta(&new, &delta), old);
+    }
+
+    #[test]
+    fn drops_oldest_frames() {
+        let mut rewind = Rewind::new(3, 1024);
+        for i in 0..10u8 {
+            rewind.push(vec![i; 8]);
+        }
+        assert_eq!(rewind.get_frames(), 2);
+        assert_eq!(rewind.pop(), Some(vec![8; 8]));
+
+        let mut rewind = Rewind::new(100, 20);
+        for i in 0..10u8 {
+            rewind.push(vec![i; 8]);
+        }
+        assert!(rewind.get_bytes() <= 20);
+    }
+}
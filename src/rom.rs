@@ -1,3 +1,4 @@
+use sha2::{Digest, Sha256};
 use std::io::{self, Read, BufReader};
 use std::fs::File;
 use std::path::PathBuf;
@@ -10,4 +11,25 @@ pub fn load(filename: &PathBuf) -> io::Result<Vec<u8>> {
     reader.read_to_end(&mut buffer)?;
 
     Ok(buffer)
-}
\ No newline at end of file
+}
+/// The SHA-256 hash of a rom, which identifies it independent of its file name.
+pub fn hash(rom: &[u8]) -> [u8; 32] {
+    Sha256::digest(rom).into()
+}
+
+/// Formats a hash as lowercase hex.
+pub fn hash_to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parses a hash formatted by `hash_to_hex`, in either case.
+pub fn hash_from_hex(text: &str) -> Option<[u8; 32]> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
@@ -0,0 +1,419 @@
+use crate::config::{Config, ProcessorMode};
+use crate::quirks::{Quirks, QuirksProfile};
+use crate::rom;
+use ini::Ini;
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+
+/// The database that comes with the emulator, see it for the format.
+const BUNDLED_DATABASE: &str = include_str!("romdb.ini");
+
+/// Entries added by the user, read after the bundled ones.
+pub const USER_DATABASE: &str = "roms.ini";
+
+/// What the database knows about a rom. Settings that aren't given are left
+/// as they are in config.ini.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub mode: Option<ProcessorMode>,
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<u32>,
+    /// Describes the controls, since every game maps the keypad differently.
+    pub key_hints: Option<String>,
+    /// On and off color.
+    pub colors: Option<(u32, u32)>,
+}
+
+impl RomInfo {
+    /// Reads an entry, printing the values that can't be parsed. `name`
+    /// identifies the entry in those messages.
+    fn parse(name: &str, get: impl Fn(&str) -> Option<String>) -> RomInfo {
+        let mut info = RomInfo {
+            title: get("title"),
+            author: get("author"),
+            key_hints: get("keys"),
+            ..RomInfo::default()
+        };
+
+        if let Some(platform) = get("platform") {
+            match ProcessorMode::from_name(&platform) {
+                Some(mode) => info.mode = Some(mode),
+                None => println!("ERROR Unknown platform {platform} for rom {name}"),
+            }
+        }
+
+        // Individual quirks change the profile, which defaults to the one of the platform
+        let mut quirks = info.mode.map(|mode| QuirksProfile::for_mode(mode).quirks());
+        if let Some(profile) = get("profile") {
+            match QuirksProfile::from_name(&profile) {
+                Some(profile) => quirks = Some(profile.quirks()),
+                None => println!("ERROR Unknown quirks profile {profile} for rom {name}"),
+            }
+        }
+        const KEYS: [&str; 8] = [
+            "shift",
+            "memory_increment",
+            "vf_reset",
+            "jump_with_vx",
+            "clipping",
+            "display_wait",
+            "memory_increment_by_x",
+            "index_overflow",
+        ];
+        for key in KEYS {
+            let Some(value) = get(key) else { continue };
+            let Ok(enabled) = value.parse() else {
+                println!("ERROR Failed to parse {key} for rom {name}: {value}");
+                continue;
+            };
+            let quirks = quirks.get_or_insert_with(Quirks::default);
+            match key {
+                "shift" => quirks.shift = enabled,
+                "memory_increment" => quirks.memory_increment = enabled,
+                "vf_reset" => quirks.vf_reset = enabled,
+                "jump_with_vx" => quirks.jump_with_vx = enabled,
+                "clipping" => quirks.clipping = enabled,
+                "display_wait" => quirks.display_wait = enabled,
+                "memory_increment_by_x" => quirks.memory_increment_by_x = enabled,
+                _ => quirks.index_overflow = enabled,
+            }
+        }
+        info.quirks = quirks;
+
+        if let Some(tickrate) = get("tickrate") {
+            match tickrate.parse() {
+                Ok(val) if val > 0 => info.tickrate = Some(val),
+                _ => println!("ERROR Failed to parse tickrate for rom {name}: {tickrate}"),
+            }
+        }
+
+        let color = |key: &str| {
+            let value = get(key)?;
+            match u32::from_str_radix(&value.replace("0x", ""), 16) {
+                Ok(val) => Some(val),
+                Err(e) => {
+                    println!("ERROR Failed to parse {key} for rom {name}: {e}");
+                    None
+                }
+            }
+        };
+        if let (Some(on_color), Some(off_color)) = (color("on_color"), color("off_color")) {
+            info.colors = Some((on_color, off_color));
+        }
+
+        info
+    }
+
+    /// Changes the settings to the ones the rom needs.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if let Some(quirks) = self.quirks {
+            config.quirks = quirks;
+        }
+        if let Some(tickrate) = self.tickrate {
+            config.instructions_per_frame = tickrate;
+        }
+        if let Some((on_color, off_color)) = self.colors {
+            config.on_color = on_color;
+            config.off_color = off_color;
+        }
+    }
+}
+
+/// Settings for known roms, looked up by the SHA-256 hash of the rom.
+#[derive(Default)]
+pub struct RomDatabase {
+    entries: HashMap<[u8; 32], RomInfo>,
+}
+
+impl RomDatabase {
+    /// Reads the bundled database and the user's additions to it.
+    pub fn build() -> RomDatabase {
+        let mut database = RomDatabase::default();
+        if let Err(e) = database.add(BUNDLED_DATABASE) {
+            println!("ERROR Failed to parse the bundled rom database: {e}");
+        }
+
+        if let Ok(text) = fs::read_to_string(USER_DATABASE) {
+            if let Err(e) = database.add(&text) {
+                println!("ERROR Failed to parse {USER_DATABASE}: {e}");
+            }
+        }
+        database
+    }
+
+    /// Adds the entries of a database in the INI format, replacing the ones
+    /// with the same hash.
+    pub fn add(&mut self, text: &str) -> Result<(), ini::ParseError> {
+        let ini = Ini::load_from_str(text)?;
+        for (section, properties) in &ini {
+            // Values outside of a section don't belong to a rom
+            let Some(section) = section else { continue };
+            let hash = match rom::hash_from_hex(section) {
+                Some(hash) => hash,
+                None => {
+                    println!("ERROR Rom database section {section} isn't a SHA-256 hash");
+                    continue;
+                }
+            };
+            let info = RomInfo::parse(section, |key| properties.get(key).map(str::to_string));
+            self.entries.insert(hash, info);
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, hash: &[u8; 32]) -> Option<&RomInfo> {
+        self.entries.get(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The mode and quirks of a platform of the community CHIP-8 database, for
+/// the platforms that can be emulated.
+fn community_platform(id: &str) -> Option<(ProcessorMode, Quirks)> {
+    let (mode, profile) = match id {
+        "originalChip8" | "hybridVIP" => (ProcessorMode::Chip8, QuirksProfile::CosmacVip),
+        "modernChip8" => {
+            let quirks = Quirks { vf_reset: false, display_wait: false, ..QuirksProfile::CosmacVip.quirks() };
+            return Some((ProcessorMode::Chip8, quirks));
+        }
+        "chip48" => (ProcessorMode::Chip8, QuirksProfile::Chip48),
+        "superchip1" => (ProcessorMode::SuperChip, QuirksProfile::SuperChip10),
+        "superchip" => (ProcessorMode::SuperChip, QuirksProfile::SuperChip11),
+        "xochip" => (ProcessorMode::XOChip, QuirksProfile::XOChip),
+        _ => return None,
+    };
+    Some((mode, profile.quirks()))
+}
+
+/// Converts the entries of programs.json from the community CHIP-8 database
+/// (https://github.com/chip-8/chip-8-database) for `roms` into sections of
+/// the rom database. It identifies roms by SHA-1, so the roms themselves
+/// are needed to find their SHA-256. Roms it doesn't know are left out.
+pub fn import_community(programs: &str, roms: &[Vec<u8>]) -> Result<Ini, String> {
+    let programs: Value = serde_json::from_str(programs).map_err(|e| e.to_string())?;
+    let programs = programs.as_array().ok_or("expected a list of programs")?;
+
+    let mut entries = HashMap::new();
+    for program in programs {
+        if let Some(roms) = program["roms"].as_object() {
+            for (sha1, entry) in roms {
+                entries.insert(sha1.to_ascii_lowercase(), (program, entry));
+            }
+        }
+    }
+
+    let mut ini = Ini::new();
+    for data in roms {
+        let Some((program, entry)) = entries.get(&rom::hash_to_hex(&Sha1::digest(data))) else {
+            continue;
+        };
+
+        let mut values = Vec::new();
+        if let Some(title) = program["title"].as_str() {
+            values.push(("title", title.to_string()));
+        }
+        if let Some(authors) = program["authors"].as_array() {
+            let authors: Vec<&str> = authors.iter().filter_map(Value::as_str).collect();
+            if !authors.is_empty() {
+                values.push(("author", authors.join(", ")));
+            }
+        }
+
+        // The platforms are listed best first
+        let platform = entry["platforms"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .find_map(|id| community_platform(id).map(|(mode, quirks)| (id, mode, quirks)));
+        if let Some((id, mode, mut quirks)) = platform {
+            let overrides = &entry["quirkyPlatforms"][id];
+            let quirk = |name: &str| overrides[name].as_bool();
+            quirks.shift = quirk("shift").unwrap_or(quirks.shift);
+            quirks.jump_with_vx = quirk("jump").unwrap_or(quirks.jump_with_vx);
+            quirks.vf_reset = quirk("logic").unwrap_or(quirks.vf_reset);
+            quirks.display_wait = quirk("vblank").unwrap_or(quirks.display_wait);
+            quirks.clipping = quirk("wrap").map_or(quirks.clipping, |wrap| !wrap);
+            let leave = quirk("memoryLeaveIUnchanged");
+            let by_x = quirk("memoryIncrementByX");
+            if leave.is_some() || by_x.is_some() {
+                let (leave, by_x) = (leave.unwrap_or(false), by_x.unwrap_or(false));
+                quirks.memory_increment = !leave && !by_x;
+                quirks.memory_increment_by_x = !leave && by_x;
+            }
+
+            values.push(("platform", mode.get_name().to_string()));
+            match QuirksProfile::matching(&quirks) {
+                Some(profile) => values.push(("profile", profile.get_name().to_string())),
+                None => {
+                    values.push(("shift", quirks.shift.to_string()));
+                    values.push(("memory_increment", quirks.memory_increment.to_string()));
+                    values.push(("vf_reset", quirks.vf_reset.to_string()));
+                    values.push(("jump_with_vx", quirks.jump_with_vx.to_string()));
+                    values.push(("clipping", quirks.clipping.to_string()));
+                    values.push(("display_wait", quirks.display_wait.to_string()));
+                    values.push(("memory_increment_by_x", quirks.memory_increment_by_x.to_string()));
+                    values.push(("index_overflow", quirks.index_overflow.to_string()));
+                }
+            }
+        }
+
+        if let Some(tickrate) = entry["tickrate"].as_u64() {
+            values.push(("tickrate", tickrate.to_string()));
+        }
+        if let Some(keys) = entry["keys"].as_object() {
+            let hints: Vec<String> = keys
+                .iter()
+                .filter_map(|(action, key)| key.as_u64().map(|key| format!("{action}={key:X}")))
+                .collect();
+            if !hints.is_empty() {
+                values.push(("keys", hints.join(" ")));
+            }
+        }
+        // Background first, then the first plane
+        let color = |index: usize| {
+            let hex = entry["colors"]["pixels"][index].as_str()?.strip_prefix('#')?;
+            Some(format!("0x{hex}"))
+        };
+        if let (Some(off_color), Some(on_color)) = (color(0), color(1)) {
+            values.push(("on_color", on_color));
+            values.push(("off_color", off_color));
+        }
+
+        let section = rom::hash_to_hex(&rom::hash(data));
+        for (key, value) in values {
+            ini.with_section(Some(section.as_str())).set(key, value);
+        }
+    }
+    Ok(ini)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splash;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn parses_entries() {
+        let mut database = RomDatabase::default();
+        let text = format!(
+            "[{}]\ntitle=Test\nauthor=Someone\nplatform=superchip\nclipping=false\ntickrate=30\nkeys=5 fires\n\
+             on_color=0xFFFFFF\noff_color=0x0\n",
+            HASH.to_uppercase()
+        );
+        database.add(&text).unwrap();
+
+        let info = database.lookup(&rom::hash_from_hex(HASH).unwrap()).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Test"));
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert_eq!(info.mode, Some(ProcessorMode::SuperChip));
+        assert_eq!(
+            info.quirks,
+            Some(Quirks { clipping: false, ..QuirksProfile::SuperChip11.quirks() })
+        );
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.key_hints.as_deref(), Some("5 fires"));
+        assert_eq!(info.colors, Some((0xFFFFFF, 0)));
+
+        // Later entries replace earlier ones
+        database.add(&format!("[{HASH}]\nprofile=chip-48\n\n[not-a-hash]\ntitle=Skipped\n")).unwrap();
+        assert_eq!(database.len(), 1);
+        let info = database.lookup(&rom::hash_from_hex(HASH).unwrap()).unwrap();
+        assert_eq!(info.title, None);
+        assert_eq!(info.mode, None);
+        assert_eq!(info.quirks, Some(QuirksProfile::Chip48.quirks()));
+
+        // Quirks that aren't true or false are left as the profile has them
+        database.add(&format!("[{HASH}]\nprofile=chip-48\nshift=yes\nclipping=1\n")).unwrap();
+        let info = database.lookup(&rom::hash_from_hex(HASH).unwrap()).unwrap();
+        assert_eq!(info.quirks, Some(QuirksProfile::Chip48.quirks()));
+    }
+
+    #[test]
+    fn bundled_database() {
+        let mut database = RomDatabase::default();
+        database.add(BUNDLED_DATABASE).unwrap();
+        let info = database.lookup(&rom::hash(&splash::SPLASH)).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Splash screen"));
+        assert_eq!(info.mode, None);
+    }
+
+    #[test]
+    fn imports_community_database() {
+        let game = vec![0x12, 0x00];
+        let demo = vec![0x00, 0xE0, 0x12, 0x00];
+        let port = vec![0x00, 0xFF, 0x12, 0x02];
+        let programs = format!(
+            r##"[
+                {{
+                    "title": "Game",
+                    "authors": ["A", "B"],
+                    "roms": {{
+                        "{}": {{
+                            "platforms": ["megachip8", "superchip"],
+                            "quirkyPlatforms": {{ "superchip": {{ "wrap": true }} }},
+                            "tickrate": 30,
+                            "keys": {{ "up": 5 }},
+                            "colors": {{ "pixels": ["#000000", "#ffffff"] }}
+                        }}
+                    }}
+                }},
+                {{
+                    "title": "Demo",
+                    "roms": {{ "{}": {{ "platforms": ["originalChip8"] }} }}
+                }},
+                {{
+                    "title": "Port",
+                    "roms": {{ "{}": {{ "platforms": ["superchip"] }} }}
+                }}
+            ]"##,
+            rom::hash_to_hex(&Sha1::digest(&game)),
+            rom::hash_to_hex(&Sha1::digest(&demo)),
+            rom::hash_to_hex(&Sha1::digest(&port))
+        );
+
+        let mut text = Vec::new();
+        import_community(&programs, &[game.clone(), demo.clone(), port.clone(), vec![0xFF]])
+            .unwrap()
+            .write_to(&mut text)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let mut database = RomDatabase::default();
+        database.add(&text).unwrap();
+        assert_eq!(database.len(), 3);
+
+        let info = database.lookup(&rom::hash(&game)).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Game"));
+        assert_eq!(info.author.as_deref(), Some("A, B"));
+        assert_eq!(info.mode, Some(ProcessorMode::SuperChip));
+        let quirks = Quirks { clipping: false, ..QuirksProfile::SuperChip11.quirks() };
+        assert_eq!(info.quirks, Some(quirks));
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.key_hints.as_deref(), Some("up=5"));
+        assert_eq!(info.colors, Some((0xFFFFFF, 0x000000)));
+
+        let info = database.lookup(&rom::hash(&demo)).unwrap();
+        assert_eq!(info.mode, Some(ProcessorMode::Chip8));
+        assert_eq!(info.quirks, Some(QuirksProfile::CosmacVip.quirks()));
+
+        let info = database.lookup(&rom::hash(&port)).unwrap();
+        assert_eq!(info.quirks, Some(QuirksProfile::SuperChip11.quirks()));
+        assert!(text.contains("profile=schip-1.1"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::config::ProcessorMode;
+use std::error::Error;
+use std::fmt;
+
+/// The first bytes of every save state.
+pub const STATE_MAGIC: &[u8; 4] = b"C8ST";
+/// Incremented whenever the layout of a save state changes.
+pub const STATE_VERSION: u8 = 3;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StateError {
+    /// The data is not a save state.
+    BadMagic,
+    /// The state was written by a different version of the emulator.
+    UnsupportedVersion(u8),
+    /// The state was saved in another mode than the processor runs in.
+    ModeMismatch { expected: ProcessorMode, found: ProcessorMode },
+    /// The state was saved while running another rom.
+    RomMismatch,
+    /// The data ends early or holds an impossible value.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {version}"),
+            StateError::ModeMismatch { expected, found } => write!(
+                f,
+                "save state is for {} but the processor runs {}",
+                found.get_name(),
+                expected.get_name()
+            ),
+            StateError::RomMismatch => write!(f, "save state belongs to another rom"),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub fn mode_to_byte(mode: ProcessorMode) -> u8 {
+    match mode {
+        ProcessorMode::Chip8 => 0,
+        ProcessorMode::SuperChip => 1,
+        ProcessorMode::XOChip => 2,
+    }
+}
+
+pub fn mode_from_byte(byte: u8) -> Option<ProcessorMode> {
+    match byte {
+        0 => Some(ProcessorMode::Chip8),
+        1 => Some(ProcessorMode::SuperChip),
+        2 => Some(ProcessorMode::XOChip),
+        _ => None,
+    }
+}
+
+/// Packs the held keys into a bitmask, bit N set while key N is held.
+pub fn keys_to_bits(keys: &[bool; 16]) -> u16 {
+    keys.iter().enumerate().fold(0, |bits, (key, pressed)| bits | (*pressed as u16) << key)
+}
+
+pub fn keys_from_bits(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = bits >> key & 1 == 1;
+    }
+    keys
+}
+
+/// Appends little endian values to a save state.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads the values written by `StateWriter`, failing with
+/// `StateError::Corrupt` when the data runs out.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < count {
+            return Err(StateError::Corrupt);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// True once every byte has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789ABCDE);
+        writer.write_u64(0x0123456789ABCDEF);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789ABCDE));
+        assert_eq!(reader.read_u64(), Ok(0x0123456789ABCDEF));
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), Err(StateError::Corrupt));
+    }
+}
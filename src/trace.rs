@@ -0,0 +1,436 @@
+use crate::debugger;
+use crate::instruction::Instruction;
+use crate::processor::Processor;
+use crate::state::{StateError, StateReader, StateWriter};
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// The first bytes of a binary trace.
+pub const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+/// Incremented whenever the layout of a binary trace changes.
+pub const TRACE_VERSION: u8 = 1;
+
+// Bit of the change mask set when I changed, the lower bits are V0-VF
+const INDEX_CHANGED: u32 = 1 << 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TraceFormat {
+    /// One line of text per instruction.
+    #[default]
+    Text,
+    /// A few bytes per instruction, for long runs.
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TraceFormat::Text => "text",
+            TraceFormat::Binary => "binary",
+        }
+    }
+}
+
+/// Selects the instructions that are traced. Empty lists select everything.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TraceFilter {
+    /// Only instructions at these addresses.
+    pub pc_ranges: Vec<RangeInclusive<usize>>,
+    /// Only opcodes starting with these hex digits, e.g. 0xD for draws.
+    pub opcode_classes: Vec<u8>,
+    /// Only the first cycles of the run.
+    pub max_cycles: Option<u64>,
+}
+
+impl TraceFilter {
+    /// Parses address ranges like `0x200-0x2ff,0x300`.
+    pub fn parse_pc_ranges(text: &str) -> Option<Vec<RangeInclusive<usize>>> {
+        text.split(',')
+            .map(|range| match range.split_once('-') {
+                Some((start, end)) => Some(debugger::parse_number(start)?..=debugger::parse_number(end)?),
+                None => {
+                    let address = debugger::parse_number(range)?;
+                    Some(address..=address)
+                }
+            })
+            .collect()
+    }
+
+    /// Parses opcode classes like `8,D,F`.
+    pub fn parse_opcode_classes(text: &str) -> Option<Vec<u8>> {
+        text.split(',')
+            .map(|class| match class.len() {
+                1 => u8::from_str_radix(class, 16).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn format_pc_ranges(&self) -> String {
+        let ranges: Vec<String> = self
+            .pc_ranges
+            .iter()
+            .map(|range| format!("{:#x}-{:#x}", range.start(), range.end()))
+            .collect();
+        ranges.join(",")
+    }
+
+    pub fn format_opcode_classes(&self) -> String {
+        let classes: Vec<String> = self.opcode_classes.iter().map(|class| format!("{class:X}")).collect();
+        classes.join(",")
+    }
+
+    fn matches(&self, cycle: u64, pc: usize, opcode: u16) -> bool {
+        self.max_cycles.is_none_or(|max_cycles| cycle < max_cycles)
+            && (self.pc_ranges.is_empty() || self.pc_ranges.iter().any(|range| range.contains(&pc)))
+            && (self.opcode_classes.is_empty() || self.opcode_classes.contains(&((opcode >> 12) as u8)))
+    }
+}
+
+/// One executed instruction and what it changed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceRecord {
+    pub frame: u64,
+    /// The number of instructions executed before this one.
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    /// The registers that changed, with their new values.
+    pub registers: Vec<(u8, u8)>,
+    /// The new value of I, if it changed.
+    pub index: Option<u16>,
+}
+
+impl fmt::Display for TraceRecord {
+    /// Formats the record as a line of a text trace, without line break.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let disassembly = match Instruction::decode(self.opcode) {
+            Some(instruction) => instruction.to_string(),
+            None => String::from("???"),
+        };
+        let mut line = format!(
+            "{:>6} {:>10} {:04x} {:04x}  {disassembly:<20}",
+            self.frame, self.cycle, self.pc, self.opcode
+        );
+        for (x, value) in &self.registers {
+            write!(line, " V{x:X}={value:02x}")?;
+        }
+        if let Some(index) = self.index {
+            write!(line, " I={index:04x}")?;
+        }
+        f.write_str(line.trim_end())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TraceError {
+    /// The data is not a binary trace.
+    BadMagic,
+    /// The trace was written by a different version of the emulator.
+    UnsupportedVersion(u8),
+    /// The data ends in the middle of a record.
+    Corrupt,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::BadMagic => write!(f, "not a binary trace"),
+            TraceError::UnsupportedVersion(version) => write!(f, "unsupported trace version {version}"),
+            TraceError::Corrupt => write!(f, "trace is corrupt"),
+        }
+    }
+}
+
+impl Error for TraceError {}
+
+impl From<StateError> for TraceError {
+    fn from(_: StateError) -> Self {
+        TraceError::Corrupt
+    }
+}
+
+/// Writes the header of a binary trace.
+pub fn binary_header() -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.write_bytes(TRACE_MAGIC);
+    writer.write_u8(TRACE_VERSION);
+    writer.into_bytes()
+}
+
+/// Encodes a record of a binary trace. Frame and cycle are stored as the
+/// difference to the previous record, which usually fits in one byte.
+pub fn encode_record(record: &TraceRecord, previous: Option<&TraceRecord>) -> Vec<u8> {
+    let (frame, cycle) = previous.map_or((0, 0), |previous| (previous.frame, previous.cycle));
+
+    let mut writer = StateWriter::new();
+    write_varint(&mut writer, record.frame - frame);
+    write_varint(&mut writer, record.cycle - cycle);
+    writer.write_u16(record.pc);
+    writer.write_u16(record.opcode);
+
+    let mut mask = record.registers.iter().fold(0, |mask, (x, _)| mask | 1 << x);
+    if record.index.is_some() {
+        mask |= INDEX_CHANGED;
+    }
+    write_varint(&mut writer, mask as u64);
+    for (_, value) in &record.registers {
+        writer.write_u8(*value);
+    }
+    if let Some(index) = record.index {
+        writer.write_u16(index);
+    }
+    writer.into_bytes()
+}
+
+/// Reads every record of a binary trace.
+pub fn decode_binary(data: &[u8]) -> Result<Vec<TraceRecord>, TraceError> {
+    let mut reader = StateReader::new(data);
+    if reader.read_bytes(TRACE_MAGIC.len()).ok() != Some(TRACE_MAGIC.as_slice()) {
+        return Err(TraceError::BadMagic);
+    }
+    let version = reader.read_u8()?;
+    if version != TRACE_VERSION {
+        return Err(TraceError::UnsupportedVersion(version));
+    }
+
+    let mut records: Vec<TraceRecord> = Vec::new();
+    while !reader.is_empty() {
+        let (frame, cycle) = records.last().map_or((0, 0), |previous| (previous.frame, previous.cycle));
+        let frame = frame + read_varint(&mut reader)?;
+        let cycle = cycle + read_varint(&mut reader)?;
+        let pc = reader.read_u16()?;
+        let opcode = reader.read_u16()?;
+        let mask = read_varint(&mut reader)?;
+        let mut registers = Vec::new();
+        for x in 0..16 {
+            if mask & 1 << x != 0 {
+                registers.push((x, reader.read_u8()?));
+            }
+        }
+        let index = match mask & INDEX_CHANGED as u64 {
+            0 => None,
+            _ => Some(reader.read_u16()?),
+        };
+        records.push(TraceRecord { frame, cycle, pc, opcode, registers, index });
+    }
+    Ok(records)
+}
+
+fn write_varint(writer: &mut StateWriter, mut value: u64) {
+    while value >= 0x80 {
+        writer.write_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    writer.write_u8(value as u8);
+}
+
+fn read_varint(reader: &mut StateReader) -> Result<u64, TraceError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(TraceError::Corrupt)
+}
+
+/// Writes a record of every executed instruction that passes the filter.
+pub struct Tracer {
+    output: Option<Box<dyn Write>>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    frame: u64,
+    cycle: u64,
+    // The state before the current instruction, if it passes the filter
+    before: Option<(u16, u16, Vec<u8>, usize)>,
+    // The last record written, binary records are relative to it
+    previous: Option<TraceRecord>,
+}
+
+impl Tracer {
+    pub fn new(mut output: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> io::Result<Tracer> {
+        if format == TraceFormat::Binary {
+            output.write_all(&binary_header())?;
+        }
+        Ok(Tracer {
+            output: Some(output),
+            format,
+            filter,
+            frame: 0,
+            cycle: 0,
+            before: None,
+            previous: None,
+        })
+    }
+
+    pub fn create(path: &Path, format: TraceFormat, filter: TraceFilter) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Tracer::new(Box::new(BufWriter::new(file)), format, filter)
+    }
+
+    /// The number of instructions executed so far.
+    pub fn get_cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Called before every instruction.
+    pub fn before_step(&mut self, processor: &Processor) {
+        let pc = processor.get_pc();
+        let ram = processor.get_ram();
+        let opcode = match (ram.get(pc), ram.get(pc + 1)) {
+            (Some(high), Some(low)) => (*high as u16) << 8 | *low as u16,
+            _ => 0,
+        };
+        self.before = match self.output.is_some() && self.filter.matches(self.cycle, pc, opcode) {
+            true => Some((pc as u16, opcode, processor.get_registers().to_vec(), processor.get_index())),
+            false => None,
+        };
+    }
+
+    /// Called after every instruction, even one that faulted.
+    pub fn after_step(&mut self, processor: &Processor) {
+        if let Some((pc, opcode, registers, index)) = self.before.take() {
+            let record = TraceRecord {
+                frame: self.frame,
+                cycle: self.cycle,
+                pc,
+                opcode,
+                registers: processor
+                    .get_registers()
+                    .iter()
+                    .zip(&registers)
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(x, (new, _))| (x as u8, *new))
+                    .collect(),
+                index: (processor.get_index() != index).then_some(processor.get_index() as u16),
+            };
+            self.write(record);
+        }
+        self.cycle += 1;
+    }
+
+    /// Called at the end of every frame.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(output) = &mut self.output {
+            if let Err(e) = output.flush() {
+                println!("ERROR Failed to write trace: {e}");
+                self.output = None;
+            }
+        }
+    }
+
+    fn write(&mut self, record: TraceRecord) {
+        let output = match &mut self.output {
+            Some(output) => output,
+            None => return,
+        };
+        let bytes = match self.format {
+            TraceFormat::Text => {
+                let mut line = String::new();
+                writeln!(line, "{record}").unwrap();
+                line.into_bytes()
+            }
+            TraceFormat::Binary => encode_record(&record, self.previous.as_ref()),
+        };
+        // Stop tracing instead of reporting the same error every instruction
+        if let Err(e) = output.write_all(&bytes) {
+            println!("ERROR Failed to write trace: {e}");
+            self.output = None;
+        }
+        self.previous = Some(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Collects the output of a tracer
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v0 := 0x12, i := 0x300, v1 += 2, jump back
+        processor.load_data(&[0x60, 0x12, 0xA3, 0x00, 0x71, 0x02, 0x12, 0x04]).unwrap();
+        let output = Shared::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()), format, filter).unwrap();
+        for _ in 0..6 {
+            tracer.before_step(&processor);
+            processor.step().unwrap();
+            tracer.after_step(&processor);
+        }
+        let data = output.0.borrow().clone();
+        data
+    }
+
+    #[test]
+    fn text_trace() {
+        let text = String::from_utf8(trace(TraceFormat::Text, TraceFilter::default())).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "     0          0 0200 6012  LD V0, 0x12          V0=12");
+        assert!(lines[1].ends_with("I=0300"));
+        assert!(lines[3].ends_with("JP 0x204"));
+    }
+
+    #[test]
+    fn filters() {
+        let filter = TraceFilter {
+            pc_ranges: TraceFilter::parse_pc_ranges("0x202-0x204").unwrap(),
+            opcode_classes: TraceFilter::parse_opcode_classes("7,a").unwrap(),
+            max_cycles: Some(5),
+        };
+        let records = decode_binary(&trace(TraceFormat::Binary, filter)).unwrap();
+        let cycles: Vec<u64> = records.iter().map(|record| record.cycle).collect();
+        assert_eq!(cycles, [1, 2, 4]);
+        assert_eq!(records[2].registers, [(1, 4)]);
+        assert!(TraceFilter::parse_opcode_classes("10").is_none());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let data = trace(TraceFormat::Binary, TraceFilter::default());
+        let records = decode_binary(&data).unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[1].index, Some(0x300));
+        assert_eq!(records[5].cycle, 5);
+        // Header, then about 7 bytes per instruction
+        assert!(data.len() < 5 + 6 * 9);
+        assert_eq!(decode_binary(&data[..data.len() - 1]), Err(TraceError::Corrupt));
+    }
+}
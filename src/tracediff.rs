@@ -0,0 +1,297 @@
+use crate::instruction::Instruction;
+use crate::processor::Processor;
+use std::fmt::{self, Write};
+
+/// One line of a reference log, the log format of other interpreters that
+/// executions are compared against.
+///
+/// A reference log has one line per executed instruction, describing the
+/// instruction and the state right after it as space separated `key=value`
+/// pairs. Lines that are empty or start with `#` are ignored. Every key but
+/// `cycle` is optional, missing values aren't compared:
+///
+/// ```text
+/// # cycle is decimal and counts from 0, everything else is hex
+/// cycle=0 pc=0200 op=6012 v0=12 v1=00 ... vf=00 i=0000 dt=00 st=00 sp=0
+/// cycle=1 pc=0202 op=a300 i=0300 m0300=00
+/// ```
+///
+/// `pc` and `op` are the address and opcode of the executed instruction,
+/// `v0`-`vf`, `i`, `dt` and `st` the registers and timers afterwards, `sp`
+/// the number of return addresses on the stack and `mNNNN` the byte at
+/// address NNNN. Without `cycle` a line follows the one before it. The
+/// emulator must run at the same number of instructions per frame as the
+/// reference for the timers to match.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct StateLine {
+    pub cycle: u64,
+    pub pc: Option<u16>,
+    pub opcode: Option<u16>,
+    pub registers: [Option<u8>; 16],
+    pub index: Option<u16>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+    pub stack_pointer: Option<u8>,
+    /// Bytes of memory, by address.
+    pub memory: Vec<(usize, u8)>,
+}
+
+impl StateLine {
+    /// Parses a line of a reference log, `previous_cycle` is the cycle of
+    /// the line before it.
+    pub fn parse(line: &str, previous_cycle: Option<u64>) -> Result<StateLine, String> {
+        let mut state = StateLine {
+            cycle: previous_cycle.map_or(0, |cycle| cycle + 1),
+            ..StateLine::default()
+        };
+        for pair in line.split_whitespace() {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got {pair}"))?;
+            let key = key.to_ascii_lowercase();
+            let invalid = || format!("invalid value for {key}: {value}");
+            let hex = |value: &str| u16::from_str_radix(value, 16).map_err(|_| invalid());
+            let byte = |value: &str| u8::from_str_radix(value, 16).map_err(|_| invalid());
+
+            match key.as_str() {
+                "cycle" => state.cycle = value.parse().map_err(|_| invalid())?,
+                "pc" => state.pc = Some(hex(value)?),
+                "op" => state.opcode = Some(hex(value)?),
+                "i" => state.index = Some(hex(value)?),
+                "dt" => state.delay_timer = Some(byte(value)?),
+                "st" => state.sound_timer = Some(byte(value)?),
+                "sp" => state.stack_pointer = Some(byte(value)?),
+                _ => {
+                    if let Some(register) = key.strip_prefix('v').filter(|register| register.len() == 1) {
+                        let x = usize::from_str_radix(register, 16).map_err(|_| format!("unknown key {key}"))?;
+                        state.registers[x] = Some(byte(value)?);
+                    } else if let Some(address) = key.strip_prefix('m') {
+                        let address = usize::from_str_radix(address, 16).map_err(|_| format!("unknown key {key}"))?;
+                        state.memory.push((address, byte(value)?));
+                    } else {
+                        return Err(format!("unknown key {key}"));
+                    }
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Describes the state of the processor after executing the instruction
+    /// `opcode` at `pc`, with the bytes at `addresses`.
+    pub fn capture(cycle: u64, pc: u16, opcode: u16, processor: &Processor, addresses: &[usize]) -> StateLine {
+        let mut registers = [None; 16];
+        for (register, value) in registers.iter_mut().zip(processor.get_registers()) {
+            *register = Some(*value);
+        }
+        let ram = processor.get_ram();
+        StateLine {
+            cycle,
+            pc: Some(pc),
+            opcode: Some(opcode),
+            registers,
+            index: Some(processor.get_index() as u16),
+            delay_timer: Some(processor.get_delay_timer()),
+            sound_timer: Some(*processor.get_sound_timer()),
+            stack_pointer: Some(processor.get_stack().len() as u8),
+            memory: addresses
+                .iter()
+                .filter_map(|address| ram.get(*address).map(|value| (*address, *value)))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for StateLine {
+    /// Formats the line in the reference log format.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = format!("cycle={}", self.cycle);
+        if let Some(pc) = self.pc {
+            write!(line, " pc={pc:04x}")?;
+        }
+        if let Some(opcode) = self.opcode {
+            write!(line, " op={opcode:04x}")?;
+        }
+        for (x, value) in self.registers.iter().enumerate() {
+            if let Some(value) = value {
+                write!(line, " v{x:x}={value:02x}")?;
+            }
+        }
+        if let Some(index) = self.index {
+            write!(line, " i={index:04x}")?;
+        }
+        if let Some(delay_timer) = self.delay_timer {
+            write!(line, " dt={delay_timer:02x}")?;
+        }
+        if let Some(sound_timer) = self.sound_timer {
+            write!(line, " st={sound_timer:02x}")?;
+        }
+        if let Some(stack_pointer) = self.stack_pointer {
+            write!(line, " sp={stack_pointer:x}")?;
+        }
+        for (address, value) in &self.memory {
+            write!(line, " m{address:04x}={value:02x}")?;
+        }
+        f.write_str(&line)
+    }
+}
+
+/// Parses a whole reference log.
+pub fn parse_log(text: &str) -> Result<Vec<StateLine>, String> {
+    let mut lines: Vec<StateLine> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let previous_cycle = lines.last().map(|state| state.cycle);
+        let state = StateLine::parse(line, previous_cycle).map_err(|e| format!("line {}: {e}", number + 1))?;
+        lines.push(state);
+    }
+    Ok(lines)
+}
+
+/// A value that differs between two logs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Difference {
+    Pc { expected: u16, actual: u16 },
+    Opcode { expected: u16, actual: u16 },
+    Register { x: usize, expected: u8, actual: u8 },
+    Index { expected: u16, actual: u16 },
+    DelayTimer { expected: u8, actual: u8 },
+    SoundTimer { expected: u8, actual: u8 },
+    StackPointer { expected: u8, actual: u8 },
+    Memory { address: usize, expected: u8, actual: u8 },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Difference::Pc { expected, actual } => write!(f, "PC: expected {expected:#06x}, got {actual:#06x}"),
+            Difference::Opcode { expected, actual } => {
+                write!(f, "opcode: expected {expected:04x}, got {actual:04x}")
+            }
+            Difference::Register { x, expected, actual } => {
+                write!(f, "V{x:X}: expected {expected:#04x}, got {actual:#04x}")
+            }
+            Difference::Index { expected, actual } => write!(f, "I: expected {expected:#06x}, got {actual:#06x}"),
+            Difference::DelayTimer { expected, actual } => write!(f, "DT: expected {expected}, got {actual}"),
+            Difference::SoundTimer { expected, actual } => write!(f, "ST: expected {expected}, got {actual}"),
+            Difference::StackPointer { expected, actual } => write!(f, "SP: expected {expected}, got {actual}"),
+            Difference::Memory { address, expected, actual } => {
+                write!(f, "RAM[{address:#06x}]: expected {expected:#04x}, got {actual:#04x}")
+            }
+        }
+    }
+}
+
+/// Lists the values present in both lines that differ.
+pub fn compare(expected: &StateLine, actual: &StateLine) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    if let (Some(expected), Some(actual)) = (expected.pc, actual.pc) {
+        if expected != actual {
+            differences.push(Difference::Pc { expected, actual });
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.opcode, actual.opcode) {
+        if expected != actual {
+            differences.push(Difference::Opcode { expected, actual });
+        }
+    }
+    for (x, (expected, actual)) in expected.registers.iter().zip(&actual.registers).enumerate() {
+        if let (Some(expected), Some(actual)) = (*expected, *actual) {
+            if expected != actual {
+                differences.push(Difference::Register { x, expected, actual });
+            }
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.index, actual.index) {
+        if expected != actual {
+            differences.push(Difference::Index { expected, actual });
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.delay_timer, actual.delay_timer) {
+        if expected != actual {
+            differences.push(Difference::DelayTimer { expected, actual });
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.sound_timer, actual.sound_timer) {
+        if expected != actual {
+            differences.push(Difference::SoundTimer { expected, actual });
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.stack_pointer, actual.stack_pointer) {
+        if expected != actual {
+            differences.push(Difference::StackPointer { expected, actual });
+        }
+    }
+    for (address, expected) in &expected.memory {
+        let actual = actual.memory.iter().find(|(other, _)| other == address);
+        if let Some((_, actual)) = actual {
+            if expected != actual {
+                differences.push(Difference::Memory { address: *address, expected: *expected, actual: *actual });
+            }
+        }
+    }
+    differences
+}
+
+/// Describes the instruction of a line, for reports.
+pub fn describe(state: &StateLine) -> String {
+    match (state.pc, state.opcode) {
+        (Some(pc), Some(opcode)) => match Instruction::decode(opcode) {
+            Some(instruction) => format!("{pc:#06x}: {opcode:04x}  {instruction}"),
+            None => format!("{pc:#06x}: {opcode:04x}  ???"),
+        },
+        (Some(pc), None) => format!("{pc:#06x}"),
+        (None, Some(opcode)) => format!("{opcode:04x}"),
+        (None, None) => String::from("unknown instruction"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessorMode;
+
+    #[test]
+    fn parses_lines() {
+        let log = "# reference\ncycle=5 pc=0200 op=6012 v0=12 i=0300 m300=ab\n\nPC=0202 DT=3c\n";
+        let lines = parse_log(log).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].cycle, 5);
+        assert_eq!(lines[0].registers[0], Some(0x12));
+        assert_eq!(lines[0].memory, [(0x300, 0xAB)]);
+        assert_eq!(lines[1].cycle, 6);
+        assert_eq!(lines[1].delay_timer, Some(60));
+        assert_eq!(lines[0].to_string(), "cycle=5 pc=0200 op=6012 v0=12 i=0300 m0300=ab");
+
+        assert_eq!(parse_log("vg=1").unwrap_err(), "line 1: unknown key vg");
+        assert!(parse_log("v0=zz").is_err());
+    }
+
+    #[test]
+    fn finds_differences() {
+        let mut processor = Processor::new(ProcessorMode::Chip8);
+        // v0 := 0x12, i := 0x300, save v0
+        processor.load_data(&[0x60, 0x12, 0xA3, 0x00, 0xF0, 0x55]).unwrap();
+        for _ in 0..3 {
+            processor.step().unwrap();
+        }
+        let actual = StateLine::capture(2, 0x204, 0xF055, &processor, &[0x300]);
+
+        let expected = StateLine::parse("cycle=2 pc=0204 op=f055 v0=12 i=0300 m0300=12", None).unwrap();
+        assert_eq!(
+            compare(&expected, &actual),
+            [
+                Difference::Index { expected: 0x300, actual: 0x301 },
+            ]
+        );
+        let expected = StateLine::parse("cycle=2 v0=11 m0300=11", None).unwrap();
+        assert_eq!(
+            compare(&expected, &actual),
+            [
+                Difference::Register { x: 0, expected: 0x11, actual: 0x12 },
+                Difference::Memory { address: 0x300, expected: 0x11, actual: 0x12 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use chip8_emulator::{
+    assembler,
+    config::ProcessorMode,
+    dump,
+    emulator::Emulator,
+    instruction::Instruction,
+    processor::Processor,
+    quirks::QuirksProfile,
+};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Rewrites the golden images from the current output instead of comparing
+/// against them, for when a change to the screen is intended.
+const UPDATE_GOLDEN: &str = "UPDATE_GOLDEN";
+
+/// A hex key held from a frame on for a number of frames.
+struct KeyPress {
+    frame: u64,
+    key: usize,
+    frames: u64,
+}
+
+/// A test rom from tests/roms, run headlessly for a number of frames and
+/// compared with a golden image of the screen it leaves.
+struct Case {
+    rom: &'static str,
+    mode: ProcessorMode,
+    quirks: QuirksProfile,
+    frames: u64,
+    keys: &'static [KeyPress],
+    /// File in tests/golden, a PBM image if it ends in .pbm and ASCII art
+    /// like `dump::framebuffer_to_ascii` otherwise.
+    golden: &'static str,
+}
+
+fn run(case: &Case) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let source_path = root.join("roms").join(format!("{}.8o", case.rom));
+    let source = fs::read_to_string(&source_path).unwrap_or_else(|e| panic!("{}: {e}", source_path.display()));
+    let rom = assembler::assemble(&source, case.mode).unwrap_or_else(|e| panic!("{}: {e}", source_path.display()));
+
+    let mut processor = Processor::new(case.mode);
+    processor.set_quirks(case.quirks.quirks());
+    processor.load_data(&rom).unwrap();
+    processor.set_seed(0);
+    let mut emulator = Emulator::new(processor);
+
+    for frame in 0..case.frames {
+        let mut keys = [false; 16];
+        for press in case.keys {
+            if (press.frame..press.frame + press.frames).contains(&frame) {
+                keys[press.key] = true;
+            }
+        }
+        let result = emulator.run_frame(&keys);
+        if let Some(e) = result.error {
+            panic!("{} faulted in frame {frame}: {e}", case.rom);
+        }
+    }
+
+    // Every test rom ends by jumping to itself, so anything else means it
+    // got stuck or needs more frames
+    let processor = emulator.get_processor();
+    let pc = processor.get_pc();
+    let opcode = u16::from_be_bytes([processor.get_ram()[pc], processor.get_ram()[pc + 1]]);
+    assert_eq!(
+        Instruction::decode(opcode),
+        Some(Instruction::Jump(pc as u16)),
+        "{} didn't finish in {} frames, it is at {pc:#06x}",
+        case.rom,
+        case.frames
+    );
+
+    let framebuffer = processor.get_framebuffer();
+    let actual = if case.golden.ends_with(".pbm") {
+        dump::framebuffer_to_pbm(framebuffer)
+    } else {
+        dump::framebuffer_to_ascii(framebuffer)
+    };
+
+    let golden_path = root.join("golden").join(case.golden);
+    if env::var_os(UPDATE_GOLDEN).is_some() {
+        fs::write(&golden_path, &actual).unwrap_or_else(|e| panic!("{}: {e}", golden_path.display()));
+        return;
+    }
+    let expected = match fs::read_to_string(&golden_path) {
+        Ok(expected) => expected,
+        Err(e) => panic!("{}: {e}, run with {UPDATE_GOLDEN}=1 to create it", golden_path.display()),
+    };
+    if expected != actual {
+        panic!(
+            "The screen of {} differs from {}, run with {UPDATE_GOLDEN}=1 if that is intended\nScreen:\n{}",
+            case.rom,
+            golden_path.display(),
+            dump::framebuffer_to_ascii(framebuffer)
+        );
+    }
+}
+
+#[test]
+fn flags() {
+    run(&Case {
+        rom: "flags",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::CosmacVip,
+        frames: 60,
+        keys: &[],
+        golden: "flags.txt",
+    });
+}
+
+#[test]
+fn quirks_cosmac_vip() {
+    run(&Case {
+        rom: "quirks",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::CosmacVip,
+        frames: 60,
+        keys: &[],
+        golden: "quirks-cosmac-vip.txt",
+    });
+}
+
+#[test]
+fn quirks_superchip() {
+    run(&Case {
+        rom: "quirks",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::SuperChip11,
+        frames: 60,
+        keys: &[],
+        golden: "quirks-schip-1.1.txt",
+    });
+}
+
+#[test]
+fn bcd() {
+    run(&Case {
+        rom: "bcd",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::CosmacVip,
+        frames: 30,
+        keys: &[],
+        golden: "bcd.txt",
+    });
+}
+
+#[test]
+fn keypad() {
+    run(&Case {
+        rom: "keypad",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::CosmacVip,
+        frames: 60,
+        keys: &[KeyPress { frame: 10, key: 0xA, frames: 3 }, KeyPress { frame: 30, key: 0x5, frames: 10 }],
+        golden: "keypad.txt",
+    });
+}
+
+#[test]
+fn wrapping() {
+    run(&Case {
+        rom: "wrapping",
+        mode: ProcessorMode::XOChip,
+        quirks: QuirksProfile::XOChip,
+        frames: 10,
+        keys: &[],
+        golden: "wrapping.pbm",
+    });
+}
+
+#[test]
+fn clipping() {
+    run(&Case {
+        rom: "wrapping",
+        mode: ProcessorMode::Chip8,
+        quirks: QuirksProfile::CosmacVip,
+        frames: 10,
+        keys: &[],
+        golden: "clipping.pbm",
+    });
+}